@@ -1,28 +1,391 @@
+use std::collections::HashMap;
 use std::fs::File;
+use std::path::{Path, PathBuf};
 
 use serde_yaml;
 
 use errors::*;
+use game_versions::GameVersions;
+use names::FriendlyNames;
+use presets::*;
+use route::Route;
+use rules::{Action, Rule};
 
 const CONFIG_FILENAME: &str = "config.yaml";
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
+    /// Name of a built-in category preset, or `auto` to match the splits file category name.
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
     pub split_on_return_to_nexus: bool,
+    /// Worlds to split on entering, in addition to the Nexus.
+    #[serde(default)]
+    pub split_on_world_entry: Vec<WorldEntry>,
+    #[serde(default)]
     pub split_on_tetromino_doors: bool,
+    /// Split on these puzzles (internal or friendly names), or on all of them with
+    /// `split_on_tetromino_doors` if empty.
+    #[serde(default)]
+    pub split_on_puzzles: Vec<String>,
+    /// YAML file mapping internal puzzle names to friendly ones.
+    #[serde(default = "default_puzzle_names_file")]
+    pub puzzle_names_file: String,
+    #[serde(default)]
     pub split_on_item_unlocks: bool,
+    #[serde(default)]
     pub split_on_sigil_collection: SigilCollectionConfig,
+    #[serde(default)]
+    pub road_to_gehenna: GehennaConfig,
+    /// What to do when the simulation is stopped, for example by quitting to the menu.
+    #[serde(default)]
+    pub on_stopping_simulation: StoppingSimulation,
+    /// What to do with the existing contents of the Talos log on startup.
+    #[serde(default)]
+    pub log_start: LogStart,
+    /// Endings which finish the run. If empty, they come from the category preset, or are all the
+    /// endings when using the options above.
+    #[serde(default)]
+    pub endings: Vec<Ending>,
+    /// Replacement triggers for the endings.
+    #[serde(default)]
+    pub ending_triggers: HashMap<Ending, EndingTrigger>,
+    /// Time the splits by the timestamps of the log lines rather than by when they are read.
+    #[serde(default = "default_log_timestamps")]
+    pub log_timestamps: bool,
+    /// Control the timer with the /tt_* console commands.
+    #[serde(default = "default_console_commands")]
+    pub console_commands: bool,
+    #[serde(default)]
+    pub route: RouteConfig,
+    /// Split rules. If empty, the rules come from the category preset or the options above.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Rules triggered by changes of the watcher values, added to the rules above or the preset.
+    #[serde(default)]
+    pub watcher_rules: Vec<Rule>,
+    /// YAML file with the memory locations for load removal in each game version.
+    #[serde(default = "default_game_versions_file")]
+    pub game_versions_file: String,
+    /// Extra friendly world names, mapped to world files.
+    #[serde(default)]
+    pub world_names: HashMap<String, String>,
+    #[serde(skip)]
+    pub names: FriendlyNames,
+    #[serde(skip)]
+    pub game_versions: GameVersions,
+    /// The world being timed in the individual level mode.
+    #[serde(skip)]
+    pub individual_level: Option<String>,
+    /// Whether the splits are being learned from the run instead of timed.
+    #[serde(skip)]
+    pub learning: bool,
+    /// The route attached to the splits file, if any.
+    #[serde(skip)]
+    pub loaded_route: Option<Route>,
+    /// Problems with the config that don't stop the timer, for showing to the user.
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+fn default_puzzle_names_file() -> String {
+    "puzzle_names.yaml".to_string()
+}
+
+fn default_game_versions_file() -> String {
+    "game_versions.yaml".to_string()
+}
+
+fn default_log_timestamps() -> bool {
+    true
+}
+
+fn default_console_commands() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogStart {
+    /// Read the existing log to rebuild the game state, then follow it.
+    Replay,
+    /// Skip the existing log and only follow the new lines.
+    End,
+}
+
+impl Default for LogStart {
+    fn default() -> Self {
+        LogStart::Replay
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoppingSimulation {
+    /// Reset the timer, saving the golds.
+    Reset,
+    /// Reset the timer without saving the golds.
+    ResetWithoutGolds,
+    /// Pause the timer until the simulation is started again.
+    Pause,
+    /// Keep the timer running.
+    Ignore,
+}
+
+impl Default for StoppingSimulation {
+    fn default() -> Self {
+        StoppingSimulation::Reset
+    }
 }
 
 #[derive(Debug, Deserialize)]
+pub struct WorldEntry {
+    pub world: String,
+    /// If set, split only when coming from this world.
+    #[serde(default)]
+    pub from: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 pub struct SigilCollectionConfig {
     pub in_general: bool,
-    pub in_a6: bool,
-    pub in_b4: bool,
+    /// If not empty, split only on sigils in these worlds.
+    pub included_worlds: Vec<String>,
+    /// Don't split on sigils in these worlds.
+    pub excluded_worlds: Vec<String>,
+    /// If not empty, split only on these sigils.
+    pub included_sigils: Vec<String>,
+    /// Don't split on these sigils.
+    pub excluded_sigils: Vec<String>,
+    /// Old option for splitting on the sigils in A6, replaced by `excluded_worlds`.
+    in_a6: Option<bool>,
+    /// Old option for splitting on the sigils in B4, replaced by `excluded_worlds`.
+    in_b4: Option<bool>,
+}
+
+impl SigilCollectionConfig {
+    /// Turns the old `in_a6` and `in_b4` options into `excluded_worlds` entries, so that existing
+    /// configs keep working.
+    fn migrate(&mut self, names: &FriendlyNames) {
+        for &(world, split) in &[("A6", self.in_a6), ("B4", self.in_b4)] {
+            let file = names.world_file(world);
+            let excluded = self.excluded_worlds
+                               .iter()
+                               .any(|x| names.world_file(x) == file);
+
+            match split {
+                Some(false) if !excluded => self.excluded_worlds.push(world.to_string()),
+                Some(true) => self.excluded_worlds.retain(|x| names.world_file(x) != file),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct GehennaConfig {
+    /// Start the timer on the DLC's opening world.
+    pub start: bool,
+    /// Split on returning to the hub after finishing an area.
+    pub split_on_area_completion: bool,
+    /// Split on reaching the DLC ending.
+    pub split_on_ending: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RouteConfig {
+    /// The route file. Defaults to the splits file with the `.route.yaml` extension.
+    pub file: Option<String>,
+    /// When an event expected for a later segment happens, skip the segments in between and
+    /// split, instead of only warning.
+    pub mark_skipped_segments: bool,
+}
+
+impl Config {
+    /// Builds the rules corresponding to the `split_on_*` options.
+    fn default_rules(&self) -> Vec<Rule> {
+        let mut rules = vec![start_on(A1)];
+
+        if self.split_on_return_to_nexus {
+            rules.push(split_on_return_to_nexus());
+        }
+
+        for entry in &self.split_on_world_entry {
+            rules.push(split_on_entering(&entry.world, entry.from.as_ref().map(|x| x.as_str())));
+        }
+
+        let sigils = &self.split_on_sigil_collection;
+        if sigils.in_general {
+            rules.push(split_on_sigils().including_worlds(sigils.included_worlds.clone())
+                                        .excluding_worlds(sigils.excluded_worlds.clone())
+                                        .including_values(sigils.included_sigils.clone())
+                                        .excluding_values(sigils.excluded_sigils.clone()));
+        }
+
+        rules.extend(gehenna_rules(&self.road_to_gehenna));
+
+        // Listing puzzles is enough to split on them, the option alone splits on all of them.
+        if self.split_on_tetromino_doors || !self.split_on_puzzles.is_empty() {
+            let puzzles = self.names.internal_puzzle_names(&self.split_on_puzzles);
+            rules.push(split_on_puzzles().including_values(puzzles));
+        }
+
+        rules
+    }
+
+    /// Makes sure the Road to Gehenna worlds are set in `world_names`, as their files aren't built
+    /// in.
+    fn check_gehenna_worlds(&self) -> Result<()> {
+        let worlds = [GEHENNA_INTRO, GEHENNA_HUB, GEHENNA_ENDING];
+        let missing = worlds.iter()
+                            .chain(GEHENNA_AREAS.iter())
+                            .filter(|x| self.names.world_file(x) == **x)
+                            .cloned()
+                            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            bail!("the Road to Gehenna world files are not known, set {} in world_names",
+                  missing.join(", "));
+        }
+
+        Ok(())
+    }
+
+    /// Endings finishing the run when using the `split_on_*` options.
+    fn default_endings(&self) -> Vec<Ending> {
+        let mut endings = vec![Ending::Transcendence, Ending::Eternalization, Ending::Messenger];
+        if self.road_to_gehenna.split_on_ending {
+            endings.push(Ending::Gehenna);
+        }
+
+        endings
+    }
+
+    /// Builds the rule finishing the run on the ending, using its configured trigger if any.
+    ///
+    /// Returns `None` for endings without a built-in trigger that isn't configured either.
+    fn ending_rule(&self, ending: Ending) -> Option<Rule> {
+        match self.ending_triggers.get(&ending) {
+            Some(custom) => {
+                let mut rule = Rule::new(custom.trigger.clone(), Action::Finish);
+                rule.conditions = custom.conditions.clone();
+                Some(rule)
+            }
+            None => ending.rule(),
+        }
+    }
+
+    /// Loads the route attached to the splits file, if there is one.
+    pub fn load_route(&mut self, splits_filename: &str) -> Result<()> {
+        let filename = match self.route.file {
+            Some(ref file) => PathBuf::from(file),
+            None => Path::new(splits_filename).with_extension("route.yaml"),
+        };
+
+        self.loaded_route = Route::load(&filename)?;
+        if self.route.file.is_some() && self.loaded_route.is_none() {
+            bail!("{} does not exist", filename.display());
+        }
+
+        Ok(())
+    }
+
+    /// Selects and compiles the split rules.
+    ///
+    /// In the individual level mode the rules are always the ones for timing the level. Otherwise
+    /// explicit rules take priority, followed by the `category` preset (with `auto`, the one
+    /// matching the splits file category name) and finally by the `split_on_*` options. Only the
+    /// selected endings finish the run; reaching any other one does nothing. The watcher rules
+    /// are added to all but the individual level rules, and the console command rules to all of
+    /// them.
+    pub fn select_rules(&mut self, splits_category_name: &str) -> Result<()> {
+        if let Some(ref world) = self.individual_level {
+            if self.names.world_file(world) == *world && !world.to_lowercase().ends_with(".wld") {
+                bail!("unknown world {:?}, use a world name (see world_names) or a .wld file",
+                      world);
+            }
+
+            self.rules = individual_level_rules(world);
+            self.rules.extend(stopping_simulation_rules(self.on_stopping_simulation));
+        } else if self.rules.is_empty() {
+            let category = Category::select(self.category.as_ref().map(|x| x.as_str()),
+                                            splits_category_name)?;
+
+            let (rules, endings) = match category {
+                Some(category) => (category.rules(), category.endings()),
+                None => (self.default_rules(), self.default_endings()),
+            };
+            let endings = if self.endings.is_empty() {
+                endings
+            } else {
+                self.endings.clone()
+            };
+
+            let uses_gehenna = match category {
+                Some(category) => category == Category::Gehenna,
+                None => self.road_to_gehenna.start || self.road_to_gehenna.split_on_area_completion,
+            };
+            let gehenna_ending = endings.contains(&Ending::Gehenna) &&
+                                 !self.ending_triggers.contains_key(&Ending::Gehenna);
+            if uses_gehenna || gehenna_ending {
+                self.check_gehenna_worlds()?;
+            }
+
+            self.rules = rules;
+            for ending in endings {
+                match self.ending_rule(ending) {
+                    Some(rule) => self.rules.push(rule),
+                    None if !self.endings.is_empty() => {
+                        bail!("the {} ending has no built-in trigger, set one in ending_triggers",
+                              ending.name())
+                    }
+                    None => {
+                        self.warnings.push(format!("the {} ending has no built-in trigger and \
+                                                    won't finish the run, set one in \
+                                                    ending_triggers",
+                                                   ending.name()))
+                    }
+                }
+            }
+            self.rules.extend(stopping_simulation_rules(self.on_stopping_simulation));
+        }
+
+        if self.individual_level.is_none() {
+            self.rules.extend(self.watcher_rules.iter().cloned());
+        }
+
+        if self.console_commands {
+            self.rules.extend(console_command_rules());
+        }
+
+        for (i, rule) in self.rules.iter_mut().enumerate() {
+            let names = &self.names;
+            rule.map_worlds(|x| names.world_file(x));
+            rule.compile()
+                .chain_err(|| format!("invalid rule #{}", i + 1))?;
+        }
+
+        if let Some(ref mut route) = self.loaded_route {
+            let names = &self.names;
+            route.compile(|x| names.world_file(x))?;
+        }
+
+        Ok(())
+    }
 }
 
 pub fn read_config() -> Result<Config> {
     let file =
         File::open(CONFIG_FILENAME).chain_err(|| format!("could not open {}", CONFIG_FILENAME))?;
-    serde_yaml::from_reader(file).chain_err(|| format!("could not parse {}", CONFIG_FILENAME))
+    let mut config: Config = serde_yaml::from_reader(file)
+        .chain_err(|| format!("could not parse {}", CONFIG_FILENAME))?;
+    config.names = FriendlyNames::load(&config.puzzle_names_file, &config.world_names)?;
+    config.split_on_sigil_collection.migrate(&config.names);
+    config.game_versions = GameVersions::load(&config.game_versions_file)?;
+
+    Ok(config)
 }
@@ -1,106 +1,441 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Instant;
+
 use chrono::Duration;
-use livesplit_core::{SharedTimer, TimeSpan, TimerPhase, TimingMethod};
+use livesplit_core::{SharedTimer, TimeSpan, Timer, TimerPhase, TimingMethod};
+use game_versions::{GameMemory, GameVersions};
+use memory::{Value, WatcherChange};
 use process_list::get_process_list;
-use read_process_memory::{CopyAddress, Pid, ProcessHandle, TryIntoProcessHandle};
+use read_process_memory::{Pid, ProcessHandle, TryIntoProcessHandle};
 use timer_crate;
 
+/// The latest values of the watchers of the game version, by name.
+pub type WatcherValues = Arc<Mutex<BTreeMap<String, Value>>>;
+
+/// How often the game memory is read.
+const POLL_INTERVAL_MS: i64 = 15;
+/// How many polls to wait between looking for the game after losing it, about a second.
+const REATTACH_INTERVAL_POLLS: u32 = 67;
+
+/// Tracks the game time of the timer by reading the loading flags from the game memory.
+///
+/// Tracking runs from `start` until `stop`, which is due when the timer is reset. Until the game
+/// is found, the timer shows the real time. If the game goes away in between, the game time is
+/// paused until it is back.
 pub struct GameTime {
-    talos_pid: Option<Pid>,
+    /// The Talos process, once found. Shared with the poller, which finds it.
+    attachment: Arc<Mutex<Option<Attachment>>>,
+    watchers: WatcherValues,
+    /// Changes of the watcher values, sent by the poller.
+    changes_tx: Sender<WatcherChange>,
+    changes_rx: Receiver<WatcherChange>,
+    /// Messages for the user, sent by the poller.
+    notices_tx: Sender<String>,
+    notices_rx: Receiver<String>,
+    /// When the game time was last resumed after a load, set by the poller.
+    resumed_at: Arc<Mutex<Option<Instant>>>,
     scheduler: timer_crate::Timer,
     thread: Option<timer_crate::Guard>,
 }
 
+/// The Talos process along with the value paths resolved for its version.
+#[derive(Debug, Clone)]
+struct Attachment {
+    pid: Pid,
+    memory: GameMemory,
+}
+
+/// The outcome of attaching to the game: the attachment and a message if the game version is
+/// unknown, or the message for the user on failure.
+type AttachResult = ::std::result::Result<(Attachment, Option<String>), String>;
+
+/// The state of the repeating job reading the game memory.
+struct Poller {
+    timer: SharedTimer,
+    versions: GameVersions,
+    /// The Talos process, or `None` while the game is gone.
+    attachment: Arc<Mutex<Option<Attachment>>>,
+    was_loading: bool,
+    /// Needed for correct handling of the intro cutscene.
+    first: bool,
+    /// Whether the game was found since the start, before which the real time is shown.
+    found: bool,
+    /// Polls left until looking for the game again.
+    reattach_countdown: u32,
+    /// The process whose memory couldn't be found, not to be tried again.
+    failed_pid: Option<Pid>,
+    /// The process being attached to on another thread, along with the receiver of the result.
+    attaching: Option<(Pid, Receiver<AttachResult>)>,
+    watchers: WatcherValues,
+    changes: Sender<WatcherChange>,
+    notices: Sender<String>,
+    resumed_at: Arc<Mutex<Option<Instant>>>,
+}
+
 impl GameTime {
-    pub fn new() -> Self {
+    pub fn new(watchers: WatcherValues) -> Self {
+        let (changes_tx, changes_rx) = channel();
+        let (notices_tx, notices_rx) = channel();
+
         Self {
-            talos_pid: None,
+            attachment: Arc::new(Mutex::new(None)),
+            watchers,
+            changes_tx,
+            changes_rx,
+            notices_tx,
+            notices_rx,
+            resumed_at: Arc::new(Mutex::new(None)),
             scheduler: timer_crate::Timer::new(),
             thread: None,
         }
     }
 
-    pub fn start(&mut self, timer: SharedTimer) {
-        let mut talos_process = self.talos_pid
-                                    .and_then(|x| x.try_into_process_handle().ok());
-        if talos_process.is_none() {
-            let pid = get_talos_pid();
-            if self.talos_pid != pid {
-                self.talos_pid = pid;
-                talos_process = self.talos_pid
-                                    .and_then(|x| x.try_into_process_handle().ok());
+    /// Starts tracking the game time, using the memory locations of the running game version.
+    ///
+    /// If `wait_for_intro` is set, the game time stays paused until the intro cutscene is over,
+    /// otherwise it starts as soon as the current loading finishes.
+    ///
+    /// Only the game found before is used right away. Otherwise the poller looks for the game and
+    /// a message saying so is returned; until the game is found, the real time is shown.
+    pub fn start(&mut self,
+                 timer: SharedTimer,
+                 wait_for_intro: bool,
+                 versions: &GameVersions)
+                 -> Option<String> {
+        self.stop();
+
+        let attached = {
+            let mut attachment = self.attachment.lock().unwrap();
+            let loading = attachment.as_ref().and_then(|x| x.is_loading());
+            if loading.is_none() {
+                *attachment = None;
             }
-        }
+            loading
+        };
 
-        let mut was_loading;
+        let message = match attached {
+            Some(_) => None,
+            None => Some("looking for Talos, timing real time until it is found".to_string()),
+        };
+        // Without the game, the game time is paused as if loading until it's found.
+        let loading = attached.unwrap_or(true);
 
         {
             let mut timer_ = timer.write();
 
-            if talos_process.is_none() {
-                timer_.set_current_timing_method(TimingMethod::RealTime);
-                return;
+            timer_.set_current_timing_method(if attached.is_some() {
+                                                 TimingMethod::GameTime
+                                             } else {
+                                                 TimingMethod::RealTime
+                                             });
+            timer_.initialize_game_time();
+            if wait_for_intro || loading {
+                timer_.pause_game_time();
             }
+            timer_.set_game_time(TimeSpan::zero());
+        }
 
-            let loading = is_loading(talos_process.as_ref().unwrap());
-            if loading.is_none() {
-                timer_.set_current_timing_method(TimingMethod::RealTime);
-                return;
+        // The values of the previous attempt are stale.
+        self.watchers.lock().unwrap().clear();
+        *self.resumed_at.lock().unwrap() = None;
+
+        let mut poller = Poller {
+            timer,
+            versions: versions.clone(),
+            attachment: self.attachment.clone(),
+            was_loading: loading,
+            first: wait_for_intro,
+            found: attached.is_some(),
+            reattach_countdown: 0,
+            failed_pid: None,
+            attaching: None,
+            watchers: self.watchers.clone(),
+            changes: self.changes_tx.clone(),
+            notices: self.notices_tx.clone(),
+            resumed_at: self.resumed_at.clone(),
+        };
+
+        self.thread = Some(self.scheduler
+                               .schedule_repeating(Duration::milliseconds(POLL_INTERVAL_MS),
+                                                   move || poller.poll()));
+
+        message
+    }
+
+    /// Stops tracking the game time, for example when the timer is reset.
+    pub fn stop(&mut self) {
+        // Dropping the guard cancels the job.
+        self.thread = None;
+    }
+
+    /// Stops tracking the game time and forgets the game, for example when it restarts.
+    pub fn reset(&mut self) {
+        self.stop();
+        *self.attachment.lock().unwrap() = None;
+    }
+
+    /// Returns whether the game time is being tracked.
+    pub fn is_running(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    /// Returns whether the game time was resumed after a load in the last `duration`.
+    pub fn resumed_within(&self, duration: TimeSpan) -> bool {
+        match *self.resumed_at.lock().unwrap() {
+            Some(resumed_at) => {
+                let elapsed = resumed_at.elapsed();
+                let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+                elapsed < duration.total_seconds()
             }
-            was_loading = loading.unwrap();
+            None => false,
+        }
+    }
 
-            timer_.set_current_timing_method(TimingMethod::GameTime);
-            timer_.initialize_game_time();
-            timer_.pause_game_time();
-            timer_.set_game_time(TimeSpan::zero());
+    /// Returns the changes of the watcher values since the last call.
+    pub fn watcher_changes(&self) -> Vec<WatcherChange> {
+        self.changes_rx.try_iter().collect()
+    }
+
+    /// Returns the messages for the user since the last call, such as about losing the game.
+    pub fn notices(&self) -> Vec<String> {
+        self.notices_rx.try_iter().collect()
+    }
+}
+
+impl Attachment {
+    /// Attaches to the process, returning a message if the game version is unknown, or the
+    /// message for the user on failure.
+    fn new(pid: Pid, versions: &GameVersions) -> AttachResult {
+        let process = pid.try_into_process_handle()
+                         .map_err(|e| format!("could not open Talos: {}", e))?;
+        let (memory, note) = find_game_memory(pid, &process, versions)?;
+
+        Ok((Self { pid, memory }, note))
+    }
+
+    /// Returns whether the game is loading, or `None` if its memory can't be read.
+    fn is_loading(&self) -> Option<bool> {
+        self.pid
+            .try_into_process_handle()
+            .ok()
+            .and_then(|x| is_loading(&x, &self.memory))
+    }
+
+    /// Reads the watchers and returns whether the game is loading, or `None` if its memory can't
+    /// be read.
+    fn poll(&self, watchers: &WatcherValues, changes: &Sender<WatcherChange>) -> Option<bool> {
+        let process = match self.pid.try_into_process_handle() {
+            Ok(process) => process,
+            Err(_) => return None,
+        };
+
+        read_watchers(&process, &self.memory, watchers, changes);
+        is_loading(&process, &self.memory)
+    }
+}
+
+impl Poller {
+    fn poll(&mut self) {
+        let loading = match *self.attachment.lock().unwrap() {
+            Some(ref attachment) => Some(attachment.poll(&self.watchers, &self.changes)),
+            None => None,
+        };
+
+        match loading {
+            Some(Some(loading)) => self.update_loading(loading),
+            Some(None) => self.detach(),
+            None => self.reattach(),
         }
+    }
 
-        // Needed for correct handling of the intro cutscene.
-        let mut first = true;
+    fn update_loading(&mut self, loading: bool) {
+        if self.was_loading == loading {
+            return;
+        }
+        self.was_loading = loading;
 
-        self.thread = Some(self.scheduler.schedule_repeating(
-            Duration::milliseconds(15),
-            move || {
-                // TODO: figure out a way to stop the repeating if an error occurrs.
-                if talos_process.is_none() {
-                    return;
-                }
+        if loading {
+            self.update_timer(|x| x.pause_game_time());
+        } else {
+            if !self.first {
+                self.update_timer(|x| x.unpause_game_time());
+                *self.resumed_at.lock().unwrap() = Some(Instant::now());
+            }
+
+            self.first = false;
+        }
+    }
+
+    /// Applies the change to the timer while its game time is tracked.
+    fn update_timer<F: FnOnce(&mut Timer)>(&self, f: F) {
+        let mut timer = self.timer.write();
+        if is_tracked(&timer) {
+            f(&mut *timer);
+        }
+    }
+
+    /// Forgets the game after it went away, pausing the game time until it is back.
+    fn detach(&mut self) {
+        *self.attachment.lock().unwrap() = None;
+        self.reattach_countdown = REATTACH_INTERVAL_POLLS;
+        // Nothing can be read, so the values would only be stale.
+        self.watchers.lock().unwrap().clear();
+
+        // Handled like loading, so the game time resumes once the game is back and not loading.
+        if !self.was_loading {
+            self.update_timer(|x| x.pause_game_time());
+        }
+        self.was_loading = true;
 
-                if let Some(loading) = is_loading(talos_process.as_ref().unwrap()) {
-                    if was_loading != loading {
-                        was_loading = loading;
-
-                        if loading {
-                            let mut timer = timer.write();
-                            if timer.current_phase() == TimerPhase::Running &&
-                                timer.is_game_time_initialized()
-                            {
-                                timer.pause_game_time();
-                            } else {
-                                talos_process = None;
-                            }
-                        } else {
-                            if !first {
-                                let mut timer = timer.write();
-                                if timer.current_phase() == TimerPhase::Running &&
-                                    timer.is_game_time_initialized()
-                                {
-                                    timer.unpause_game_time();
-                                } else {
-                                    talos_process = None;
-                                }
-                            }
-
-                            first = false;
-                        }
+        let _ = self.notices.send("lost Talos, game time paused until it is back".to_string());
+    }
+
+    /// Starts reading the memory of the game found on another thread.
+    fn attach(&mut self, attachment: Attachment) {
+        let loading = attachment.is_loading().unwrap_or(true);
+
+        if !self.found {
+            self.found = true;
+            let first = self.first;
+            self.update_timer(|x| {
+                x.set_current_timing_method(TimingMethod::GameTime);
+                // The loads before the game was found are unknown, so that time all counts.
+                if !first {
+                    if let Some(real_time) = x.current_time().real_time {
+                        x.set_game_time(real_time);
                     }
-                } else {
-                    talos_process = None;
                 }
-            },
-        ));
+            });
+        }
+
+        // Whatever happened while the game was missing, the game time now follows its state.
+        self.was_loading = !loading;
+        self.update_loading(loading);
+        *self.attachment.lock().unwrap() = Some(attachment);
+    }
+
+    /// Looks for the game every once in a while.
+    ///
+    /// Attaching hashes the executable and might scan it for signatures, which takes far longer
+    /// than a poll, so it's done on another thread and picked up by a later poll.
+    fn reattach(&mut self) {
+        if let Some((pid, receiver)) = self.attaching.take() {
+            let received = receiver.try_recv();
+            let message = match received {
+                Ok(Ok((attachment, note))) => {
+                    let found = if self.found {
+                        "found Talos again"
+                    } else {
+                        "found Talos"
+                    };
+                    self.attach(attachment);
+                    note.unwrap_or_else(|| found.to_string())
+                }
+                Ok(Err(note)) => {
+                    self.failed_pid = Some(pid);
+                    note
+                }
+                Err(TryRecvError::Empty) => {
+                    self.attaching = Some((pid, receiver));
+                    return;
+                }
+                // The thread died, so try again later.
+                Err(TryRecvError::Disconnected) => return,
+            };
+            let _ = self.notices.send(message);
+            return;
+        }
+
+        if self.reattach_countdown > 0 {
+            self.reattach_countdown -= 1;
+            return;
+        }
+        self.reattach_countdown = REATTACH_INTERVAL_POLLS;
+
+        let pid = match get_talos_pid() {
+            Some(pid) if Some(pid) != self.failed_pid => pid,
+            _ => return,
+        };
+
+        let (tx, rx) = channel();
+        let versions = self.versions.clone();
+        thread::spawn(move || {
+                          let _ = tx.send(Attachment::new(pid, &versions));
+                      });
+        self.attaching = Some((pid, rx));
+    }
+}
+
+/// Finds the value paths for the version of the running game, along with a message if the
+/// version is unknown. Returns the message for the user on failure.
+fn find_game_memory(pid: Pid,
+                    process: &ProcessHandle,
+                    versions: &GameVersions)
+                    -> ::std::result::Result<(GameMemory, Option<String>), String> {
+    let hash = versions.executable_hash(pid).unwrap_or_default();
+
+    let version = match versions.find(&hash) {
+        Some(version) => version,
+        None => return Err(format!("unknown Talos version {}, no load removal", hash)),
+    };
+
+    let memory = version.resolve(pid, process, versions.cache_filename())
+                        .map_err(|e| format!("no load removal: {}", e))?;
+
+    let mut notes = Vec::new();
+    if version.hash.is_none() && !hash.is_empty() {
+        notes.push(format!("unknown Talos version {}, trying {}", hash, version.name));
+    }
+    if !memory.unresolved_watchers.is_empty() {
+        notes.push(format!("skipped watchers {}", memory.unresolved_watchers.join(", ")));
+    }
+    let message = if notes.is_empty() {
+        None
+    } else {
+        Some(notes.join("; "))
+    };
+
+    Ok((memory, message))
+}
+
+/// Reads the values of the watchers and sends the changes of the values that were read before.
+///
+/// The values that can't be read keep their last known value, for example while a pointer is
+/// changing during a load, so the change is still sent once they can be read again.
+fn read_watchers(process: &ProcessHandle,
+                 memory: &GameMemory,
+                 watchers: &WatcherValues,
+                 changes: &Sender<WatcherChange>) {
+    let mut watchers = watchers.lock().unwrap();
+    for &(ref name, ref path) in &memory.watchers {
+        let value = match path.read(process) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        if let Some(old) = watchers.insert(name.clone(), value.clone()) {
+            if old != value {
+                let _ = changes.send(WatcherChange {
+                                         name: name.clone(),
+                                         old,
+                                         new: value,
+                                     });
+            }
+        }
     }
 }
 
+/// Returns whether the game time of the timer should still be tracked.
+///
+/// The timer can be paused, for example while the game is in the menu.
+fn is_tracked(timer: &Timer) -> bool {
+    (timer.current_phase() == TimerPhase::Running || timer.current_phase() == TimerPhase::Paused) &&
+        timer.is_game_time_initialized()
+}
+
 fn get_talos_pid() -> Option<Pid> {
     let mut iter = get_process_list().into_iter()
                                      .filter(|&(_, ref v)| v.ends_with("/x64/Talos"));
@@ -117,53 +452,17 @@ fn get_talos_pid() -> Option<Pid> {
 }
 
 #[cfg(all(not(windows), not(target_os = "macos")))]
-fn is_loading(process: &ProcessHandle) -> Option<bool> {
-    // const LOADING_POPUP_PTR: u64 = 0x2B2CAC0;
-    // const LOADING_WORLD_PTR: u64 = 0x2B961C0;
-    const LOADING_POPUP_PTR: u64 = 0x2C82BA0;
-    const LOADING_WORLD_PTR: u64 = 0x2CEC320;
-    const OFF: u64 = 0x58;
-
-    let mut buf = [0u8; 8];
-    if process.copy_address(LOADING_POPUP_PTR as usize, &mut buf)
-              .is_err()
-    {
-        return None;
-    }
-    let ptr = unsafe { *(buf.as_ptr() as *const u64) };
-    let mut buf = [0u8; 1];
-    if process.copy_address((ptr + OFF) as usize, &mut buf)
-              .is_err()
-    {
-        return None;
-    }
-    let load_popup = unsafe { *(buf.as_ptr() as *const u8) };
-    if load_popup & 1 != 0 {
-        return Some(true);
-    }
-
-    let mut buf = [0u8; 8];
-    if process.copy_address(LOADING_WORLD_PTR as usize, &mut buf)
-              .is_err()
-    {
-        return None;
-    }
-    let ptr = unsafe { *(buf.as_ptr() as *const u64) };
-    let mut buf = [0u8; 1];
-    if process.copy_address((ptr + OFF) as usize, &mut buf)
-              .is_err()
-    {
-        return None;
-    }
-    let load_world = unsafe { *(buf.as_ptr() as *const u8) };
-    if load_world & 1 != 0 {
-        return Some(true);
-    }
-
-    Some(false)
+fn is_loading(process: &ProcessHandle, memory: &GameMemory) -> Option<bool> {
+    match memory.loading_popup.read(process) {
+        Some(ref x) if x.is_set() => return Some(true),
+        Some(_) => {}
+        None => return None,
+    }
+
+    memory.loading_world.read(process).map(|x| x.is_set())
 }
 
 #[cfg(not(all(not(windows), not(target_os = "macos"))))]
-fn is_loading(_: &ProcessHandle) -> Option<bool> {
+fn is_loading(_: &ProcessHandle, _: &GameMemory) -> Option<bool> {
     None
 }
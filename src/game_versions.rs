@@ -0,0 +1,404 @@
+use std::cmp::min;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use read_process_memory::{CopyAddress, Pid, ProcessHandle};
+use libc;
+use serde_yaml;
+
+use errors::*;
+use memory::{executable_regions, module_bases, ValuePath};
+
+/// Memory locations used for load removal in one build of the game.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GameVersion {
+    /// Description of the build, for example the patch number.
+    pub name: String,
+    /// Hash of the game executable. Versions without one are used for unknown builds.
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// Flag set while the loading popup is shown.
+    pub loading_popup: Location,
+    /// Flag set while a world is being loaded.
+    pub loading_world: Location,
+    /// Other values to read from the game memory, by name.
+    #[serde(default)]
+    pub watchers: BTreeMap<String, ValuePath>,
+}
+
+/// Where to find a value in the game memory.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Location {
+    Path(ValuePath),
+    Signature(Signature),
+}
+
+/// Code referencing a global pointer, for finding it in any build of the game.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Signature {
+    /// Hex bytes separated by spaces, with `??` for bytes that can be anything.
+    pub signature: String,
+    /// Position in the signature of the 32-bit displacement pointing to the global.
+    pub displacement: usize,
+    /// Whether the displacement is relative to the end of the instruction, as with RIP-relative
+    /// addressing. Otherwise it is an absolute address.
+    #[serde(default = "default_relative")]
+    pub relative: bool,
+    /// Position in the signature of the end of the instruction. Defaults to right after the
+    /// displacement.
+    #[serde(default)]
+    pub instruction_end: Option<usize>,
+    /// Offsets following the global, as in a value path. The flag is the lowest bit of the byte
+    /// at the end.
+    #[serde(default)]
+    pub offsets: Vec<u64>,
+}
+
+fn default_relative() -> bool {
+    true
+}
+
+/// The resolved value paths of a version in a running game.
+#[derive(Debug, Clone)]
+pub struct GameMemory {
+    pub loading_popup: ValuePath,
+    pub loading_world: ValuePath,
+    pub watchers: Vec<(String, ValuePath)>,
+    /// The watchers that couldn't be resolved, with the reason.
+    pub unresolved_watchers: Vec<String>,
+}
+
+/// The hash of an executable and the addresses found by signature scanning in it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CachedAddresses {
+    #[serde(default)]
+    hash: String,
+    /// The version whose signatures were scanned for.
+    #[serde(default)]
+    version: String,
+    loading_popup: Option<u64>,
+    loading_world: Option<u64>,
+}
+
+const SIGNATURE_CACHE_FILENAME: &str = "signature_cache.yaml";
+const SCAN_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The known builds of the game.
+#[derive(Debug, Clone)]
+pub struct GameVersions {
+    versions: Vec<GameVersion>,
+    /// Where the hashes and the scanned addresses of the executables are cached.
+    cache_filename: PathBuf,
+}
+
+impl Default for GameVersions {
+    /// The build the load removal was written for.
+    fn default() -> Self {
+        Self {
+            versions: vec![GameVersion {
+                               name: "built-in".to_string(),
+                               hash: None,
+                               loading_popup: Location::Path(ValuePath::flag(0x2C82BA0,
+                                                                             &[0x58])),
+                               loading_world: Location::Path(ValuePath::flag(0x2CEC320,
+                                                                             &[0x58])),
+                               watchers: BTreeMap::new(),
+                           }],
+            cache_filename: PathBuf::from(SIGNATURE_CACHE_FILENAME),
+        }
+    }
+}
+
+impl GameVersions {
+    /// Loads the builds from a YAML file with a list of versions. The cache of the executables
+    /// is kept next to it.
+    ///
+    /// If the file doesn't exist, only the built-in version is known.
+    pub fn load(filename: &str) -> Result<Self> {
+        let versions = match File::open(filename) {
+            Ok(file) => {
+                serde_yaml::from_reader(file).chain_err(|| format!("could not parse {}", filename))?
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Self::default().versions,
+            Err(e) => return Err(e).chain_err(|| format!("could not open {}", filename)),
+        };
+
+        Ok(Self {
+               versions,
+               cache_filename: Path::new(filename).with_file_name(SIGNATURE_CACHE_FILENAME),
+           })
+    }
+
+    pub fn cache_filename(&self) -> &Path {
+        &self.cache_filename
+    }
+
+    /// Returns the hash identifying the executable of the process, as a hex string.
+    ///
+    /// Hashing the whole executable takes a while, so the hash is cached by the identity of the
+    /// file.
+    pub fn executable_hash(&self, pid: Pid) -> io::Result<String> {
+        let key = executable_key(pid)?;
+        let mut cache = read_signature_cache(&self.cache_filename);
+        if let Some(cached) = cache.get(&key) {
+            if !cached.hash.is_empty() {
+                return Ok(cached.hash.clone());
+            }
+        }
+
+        let hash = hash_executable(pid)?;
+        cache.entry(key).or_insert_with(CachedAddresses::default).hash = hash.clone();
+        write_signature_cache(&self.cache_filename, &cache);
+
+        Ok(hash)
+    }
+
+    /// Finds the version with the given executable hash, falling back to the first version
+    /// without a hash.
+    pub fn find(&self, hash: &str) -> Option<&GameVersion> {
+        let hash = hash.to_lowercase();
+        self.versions
+            .iter()
+            .find(|x| x.hash.as_ref().map(|x| x.to_lowercase() == hash).unwrap_or(false))
+            .or_else(|| self.versions.iter().find(|x| x.hash.is_none()))
+    }
+}
+
+impl GameVersion {
+    /// Resolves the value paths in the process, scanning for the signatures unless the
+    /// addresses for its executable are in the cache.
+    pub fn resolve(&self,
+                   pid: Pid,
+                   process: &ProcessHandle,
+                   cache_filename: &Path)
+                   -> Result<GameMemory> {
+        let module_bases = module_bases(pid)?;
+
+        let key = executable_key(pid).ok();
+        let mut cache = read_signature_cache(cache_filename);
+        let mut cached = key.as_ref().and_then(|x| cache.remove(x)).unwrap_or_default();
+        if cached.version != self.name {
+            cached.version = self.name.clone();
+            cached.loading_popup = None;
+            cached.loading_world = None;
+        }
+
+        let loading_popup = self.loading_popup
+                                .resolve(pid, process, &module_bases, &mut cached.loading_popup)
+                                .chain_err(|| "could not find the loading popup flag")?;
+        let loading_world = self.loading_world
+                                .resolve(pid, process, &module_bases, &mut cached.loading_world)
+                                .chain_err(|| "could not find the loading world flag")?;
+
+        let mut watchers = Vec::new();
+        let mut unresolved_watchers = Vec::new();
+        for (name, path) in &self.watchers {
+            // A broken watcher is skipped, so it doesn't take the load removal down with it.
+            match path.resolve(&module_bases) {
+                Ok(path) => watchers.push((name.clone(), path)),
+                Err(e) => unresolved_watchers.push(format!("{} ({})", name, e)),
+            }
+        }
+
+        if let Some(key) = key {
+            if cached.loading_popup.is_some() || cached.loading_world.is_some() {
+                cache.insert(key, cached);
+                write_signature_cache(cache_filename, &cache);
+            }
+        }
+
+        Ok(GameMemory {
+               loading_popup,
+               loading_world,
+               watchers,
+               unresolved_watchers,
+           })
+    }
+}
+
+impl Location {
+    /// Returns the resolved value path of the location. For signatures, `address` holds the
+    /// address of the global if it's already known, and is set to it otherwise.
+    fn resolve(&self,
+               pid: Pid,
+               process: &ProcessHandle,
+               module_bases: &HashMap<String, u64>,
+               address: &mut Option<u64>)
+               -> Result<ValuePath> {
+        match *self {
+            Location::Path(ref path) => path.resolve(module_bases),
+            Location::Signature(ref signature) => {
+                let global = match *address {
+                    Some(global) => global,
+                    None => signature.scan(pid, process)?,
+                };
+                *address = Some(global);
+
+                Ok(ValuePath::flag(global, &signature.offsets))
+            }
+        }
+    }
+}
+
+impl Signature {
+    fn pattern(&self) -> Result<Vec<Option<u8>>> {
+        let pattern = self.signature
+                          .split_whitespace()
+                          .map(|x| if x.chars().all(|c| c == '?') {
+                                   Ok(None)
+                               } else {
+                                   u8::from_str_radix(x, 16).map(Some)
+                               })
+                          .collect::<::std::result::Result<Vec<_>, _>>()
+                          .chain_err(|| format!("invalid signature {:?}", self.signature))?;
+
+        if self.displacement + 4 > pattern.len() {
+            bail!("the displacement is outside of the signature {:?}", self.signature);
+        }
+
+        Ok(pattern)
+    }
+
+    /// Scans the memory of the game executable for the signature, returning the address of the
+    /// global.
+    fn scan(&self, pid: Pid, process: &ProcessHandle) -> Result<u64> {
+        let pattern = self.pattern()?;
+        let mut buf = vec![0u8; SCAN_CHUNK_SIZE];
+
+        for (start, end) in executable_regions(pid)? {
+            let mut position = start;
+            while position < end {
+                let length = min(SCAN_CHUNK_SIZE as u64, end - position) as usize;
+                if process.copy_address(position as usize, &mut buf[..length]).is_err() {
+                    break;
+                }
+
+                if let Some(index) = find_pattern(&buf[..length], &pattern) {
+                    return Ok(self.global_address(&buf[index..], position + index as u64));
+                }
+
+                if position + (length as u64) >= end || length < pattern.len() {
+                    break;
+                }
+                // Overlap the chunks so that signatures crossing them are found.
+                position += (length - pattern.len() + 1) as u64;
+            }
+        }
+
+        bail!("signature {:?} not found", self.signature)
+    }
+
+    /// Decodes the address of the global from the code matching the signature at `address`.
+    fn global_address(&self, code: &[u8], address: u64) -> u64 {
+        let bytes = &code[self.displacement..self.displacement + 4];
+        let displacement = (bytes[0] as u32) | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 |
+                           (bytes[3] as u32) << 24;
+
+        if self.relative {
+            let instruction_end = self.instruction_end.unwrap_or(self.displacement + 4) as u64;
+            (address + instruction_end).wrapping_add(displacement as i32 as i64 as u64)
+        } else {
+            displacement as u64
+        }
+    }
+}
+
+fn find_pattern(haystack: &[u8], pattern: &[Option<u8>]) -> Option<usize> {
+    if haystack.len() < pattern.len() {
+        return None;
+    }
+
+    (0..haystack.len() - pattern.len() + 1).find(|&i| {
+        pattern.iter()
+               .zip(&haystack[i..])
+               .all(|(p, &b)| p.map(|x| x == b).unwrap_or(true))
+    })
+}
+
+fn read_signature_cache(filename: &Path) -> HashMap<String, CachedAddresses> {
+    File::open(filename).ok()
+                        .and_then(|x| serde_yaml::from_reader(x).ok())
+                        .unwrap_or_default()
+}
+
+/// Writes the cache, ignoring errors: the cache only saves time.
+fn write_signature_cache(filename: &Path, cache: &HashMap<String, CachedAddresses>) {
+    if let Ok(file) = File::create(filename) {
+        if serde_yaml::to_writer(file, cache).is_ok() {
+            give_to_directory_owner(filename);
+        }
+    }
+}
+
+/// Returns a key identifying the executable of the process by its file, which unlike the hash is
+/// quick to get.
+#[cfg(all(not(windows), not(target_os = "macos")))]
+fn executable_key(pid: Pid) -> io::Result<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(format!("/proc/{}/exe", pid))?;
+    Ok(format!("{}:{}:{}:{}",
+               metadata.dev(),
+               metadata.ino(),
+               metadata.len(),
+               metadata.mtime()))
+}
+
+#[cfg(not(all(not(windows), not(target_os = "macos"))))]
+fn executable_key(_: Pid) -> io::Result<String> {
+    Err(io::Error::new(io::ErrorKind::Other, "not supported"))
+}
+
+/// Hands the file over to the owner of its directory when running as root, so that running
+/// under sudo doesn't leave a file the user can't change.
+#[cfg(all(not(windows), not(target_os = "macos")))]
+fn give_to_directory_owner(filename: &Path) {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+
+    let directory = match filename.parent() {
+        Some(directory) if directory != Path::new("") => directory,
+        _ => Path::new("."),
+    };
+
+    if let (Ok(metadata), Ok(filename)) = (fs::metadata(directory),
+                                           CString::new(filename.as_os_str().as_bytes())) {
+        // Failing to do so only means the file stays owned by root.
+        unsafe {
+            libc::chown(filename.as_ptr(), metadata.uid(), metadata.gid());
+        }
+    }
+}
+
+#[cfg(not(all(not(windows), not(target_os = "macos"))))]
+fn give_to_directory_owner(_: &Path) {}
+
+/// Hashes the executable of the process.
+fn hash_executable(pid: Pid) -> io::Result<String> {
+    let mut file = File::open(format!("/proc/{}/exe", pid))?;
+
+    // 64-bit FNV-1a, which is stable and needs no dependencies.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let length = file.read(&mut buf)?;
+        if length == 0 {
+            break;
+        }
+
+        for &byte in &buf[..length] {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    Ok(format!("{:016x}", hash))
+}
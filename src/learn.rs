@@ -0,0 +1,101 @@
+use std::fs::File;
+
+use errors::*;
+use livesplit_core::{Run, Segment, Time, TimeSpan, Timer};
+use livesplit_core::run::saver;
+
+const GAME_NAME: &str = "The Talos Principle";
+
+/// Records the split times of a run for writing them as a new splits file.
+#[derive(Debug, Default)]
+pub struct Learner {
+    segments: Vec<(String, Time)>,
+    saved: bool,
+}
+
+impl Learner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets the recorded segments, for example when a new attempt starts.
+    pub fn clear(&mut self) {
+        self.segments.clear();
+        self.saved = false;
+    }
+
+    /// Records a segment ending at `time`, or `lag` earlier.
+    pub fn record(&mut self, name: String, time: Time, lag: Option<TimeSpan>) {
+        let time = match lag {
+            Some(lag) => {
+                Time::new().with_real_time(difference(time.real_time, Some(lag)))
+                           .with_game_time(difference(time.game_time, Some(lag)))
+            }
+            None => time,
+        };
+
+        self.segments.push((name, time));
+    }
+
+    pub fn undo(&mut self) {
+        self.segments.pop();
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Returns true if the splits file was written since the attempt started.
+    pub fn is_saved(&self) -> bool {
+        self.saved
+    }
+
+    /// Writes the recorded segments to a splits file, with their times as the personal best and
+    /// the best segments.
+    pub fn save(&mut self, category_name: &str, filename: &str) -> Result<()> {
+        let mut run = Run::new();
+        run.set_game_name(GAME_NAME);
+        run.set_category_name(category_name);
+
+        let mut previous = Time::new().with_real_time(Some(TimeSpan::zero()))
+                                      .with_game_time(Some(TimeSpan::zero()));
+        for &(ref name, time) in &self.segments {
+            let segment_time =
+                Time::new().with_real_time(difference(time.real_time, previous.real_time))
+                           .with_game_time(difference(time.game_time, previous.game_time));
+
+            let mut segment = Segment::new(name.as_str());
+            segment.set_personal_best_split_time(time);
+            segment.set_best_segment_time(segment_time);
+            run.push_segment(segment);
+
+            previous = time;
+        }
+
+        saver::livesplit::save(&run,
+                               File::create(filename)
+                                   .chain_err(|| "could not open the splits file for writing")?)
+            .chain_err(|| "could not save the learned splits")?;
+        self.saved = true;
+
+        Ok(())
+    }
+}
+
+fn difference(time: Option<TimeSpan>, previous: Option<TimeSpan>) -> Option<TimeSpan> {
+    match (time, previous) {
+        (Some(time), Some(previous)) => Some(time - previous),
+        _ => None,
+    }
+}
+
+/// Creates a timer with a single placeholder segment, which is never split, for timing the run
+/// being learned.
+pub fn create_timer(category_name: &str) -> Result<Timer> {
+    let mut run = Run::new();
+    run.set_game_name(GAME_NAME);
+    run.set_category_name(category_name);
+    run.push_segment(Segment::new("Learning"));
+
+    Timer::new(run).chain_err(|| "could not create the Timer")
+}
@@ -0,0 +1,111 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use errors::*;
+
+/// A Talos log file being tailed.
+///
+/// The game truncates, deletes or recreates the log when it starts, so the file is reopened
+/// whenever that is noticed, instead of reading from a stale offset. A missing file is treated as
+/// empty until it appears.
+pub struct LogFile {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    position: u64,
+    id: u64,
+}
+
+impl LogFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut log = Self {
+            path: path.as_ref().to_path_buf(),
+            reader: None,
+            position: 0,
+            id: 0,
+        };
+        log.reopen()?;
+
+        Ok(log)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        self.reader = None;
+        self.position = 0;
+        self.id = 0;
+
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).chain_err(|| "could not open the Talos log file"),
+        };
+
+        self.id = file.metadata()
+                      .map(|x| file_id(&x))
+                      .chain_err(|| "could not get the Talos log file metadata")?;
+        self.reader = Some(BufReader::new(file));
+
+        Ok(())
+    }
+
+    /// Skips the existing contents of the file.
+    pub fn seek_to_end(&mut self) -> Result<()> {
+        if let Some(ref mut reader) = self.reader {
+            self.position = reader.seek(SeekFrom::End(0))
+                                  .chain_err(|| "could not seek in the Talos log file")?;
+        }
+
+        Ok(())
+    }
+
+    /// Reopens the file from the start if it was created, truncated or replaced since the last
+    /// read.
+    ///
+    /// Returns true if the file was reopened.
+    pub fn reopen_if_replaced(&mut self) -> Result<bool> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // The file is gone for now, keep reading the old one until it is recreated.
+            Err(_) => return Ok(false),
+        };
+
+        if self.reader.is_some() && file_id(&metadata) == self.id &&
+            metadata.len() >= self.position
+        {
+            return Ok(false);
+        }
+
+        self.reopen()?;
+        Ok(true)
+    }
+
+    /// Reads a line into `line`, returning the number of bytes read.
+    pub fn read_line(&mut self, line: &mut String) -> Result<usize> {
+        let length = match self.reader {
+            Some(ref mut reader) => {
+                reader.read_line(line)
+                      .chain_err(|| "error reading the Talos log file")?
+            }
+            None => 0,
+        };
+        self.position += length as u64;
+
+        Ok(length)
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn file_id(_: &Metadata) -> u64 {
+    0
+}
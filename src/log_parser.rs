@@ -0,0 +1,159 @@
+use std::cmp::min;
+
+use chrono::{Duration, NaiveTime};
+use regex::Regex;
+
+/// An event parsed out of a Talos log line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogEvent {
+    /// The game is changing over to the given world.
+    WorldChanged(String),
+    /// A puzzle with the given name was solved.
+    PuzzleSolved(String),
+    /// An item (a sigil, a star or an unlock) with the given name was picked up.
+    ItemPicked(String),
+    /// The simulation was started on the given world.
+    SimulationStarted(String),
+    /// The simulation was stopped, for example by quitting to the menu.
+    SimulationStopped,
+    /// The user entered the given text into the console.
+    UserCommand(String),
+    /// The game requested a delayed progress save.
+    ProgressSaved,
+}
+
+/// Parses a single Talos log line.
+pub fn parse_line(line: &str) -> Option<LogEvent> {
+    lazy_static! {
+        static ref CHANGING_OVER_TO: Regex =
+            Regex::new(r"Changing over to (.+?)\s*$").unwrap();
+        static ref PICKED: Regex =
+            Regex::new(r"Picked:\s*(.*?)\s*$").unwrap();
+        static ref PUZZLE_SOLVED: Regex =
+            Regex::new(r#"Puzzle "([^"]+)" solved"#).unwrap();
+        static ref STARTED_SIMULATION: Regex =
+            Regex::new(r"Started simulation on '([^']+)'").unwrap();
+        static ref USER: Regex =
+            Regex::new(r"USER:\s*(.*?)\s*$").unwrap();
+    }
+
+    if let Some(caps) = CHANGING_OVER_TO.captures(line) {
+        Some(LogEvent::WorldChanged(caps[1].to_string()))
+    } else if let Some(caps) = PICKED.captures(line) {
+        Some(LogEvent::ItemPicked(caps[1].to_string()))
+    } else if let Some(caps) = PUZZLE_SOLVED.captures(line) {
+        Some(LogEvent::PuzzleSolved(caps[1].to_string()))
+    } else if let Some(caps) = STARTED_SIMULATION.captures(line) {
+        Some(LogEvent::SimulationStarted(caps[1].to_string()))
+    } else if line.contains("Save Talos Progress: delayed request") {
+        Some(LogEvent::ProgressSaved)
+    } else if line.contains("Stopping simulation (duration: ") {
+        Some(LogEvent::SimulationStopped)
+    } else if let Some(caps) = USER.captures(line) {
+        Some(LogEvent::UserCommand(caps[1].to_string()))
+    } else {
+        None
+    }
+}
+
+/// Parses the time of day at the start of a Talos log line, if it has one.
+///
+/// Returns the time along with its resolution, as the game might log only whole seconds.
+pub fn parse_timestamp(line: &str) -> Option<(NaiveTime, Duration)> {
+    lazy_static! {
+        static ref TIMESTAMP: Regex =
+            Regex::new(r"^\s*(\d{1,2}):(\d{2}):(\d{2})(?:[.,](\d+))?\b").unwrap();
+    }
+
+    let caps = match TIMESTAMP.captures(line) {
+        Some(caps) => caps,
+        None => return None,
+    };
+
+    // The regex makes sure that all numbers parse.
+    let (milliseconds, resolution) = match caps.get(4) {
+        Some(fraction) => {
+            // Anything below milliseconds is not needed.
+            let digits = &fraction.as_str()[..min(fraction.as_str().len(), 3)];
+            let scale = 10u32.pow(3 - digits.len() as u32);
+            (digits.parse::<u32>().unwrap() * scale, Duration::milliseconds(scale as i64))
+        }
+        None => (0, Duration::seconds(1)),
+    };
+
+    NaiveTime::from_hms_milli_opt(caps[1].parse().unwrap(),
+                                  caps[2].parse().unwrap(),
+                                  caps[3].parse().unwrap(),
+                                  milliseconds)
+        .map(|x| (x, resolution))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_world_changes() {
+        assert_eq!(parse_line("12:00:01 INF:  Changing over to Content/Talos/Levels/Nexus.wld"),
+                   Some(LogEvent::WorldChanged("Content/Talos/Levels/Nexus.wld".to_string())));
+    }
+
+    #[test]
+    fn parses_solved_puzzles() {
+        assert_eq!(parse_line(r#"12:00:01 INF:  Puzzle "A1-Peephole" solved"#),
+                   Some(LogEvent::PuzzleSolved("A1-Peephole".to_string())));
+    }
+
+    #[test]
+    fn parses_picked_items() {
+        assert_eq!(parse_line("12:00:01 INF:  Picked: DJ3 "),
+                   Some(LogEvent::ItemPicked("DJ3".to_string())));
+    }
+
+    #[test]
+    fn parses_simulation_start() {
+        let line = "12:00:01 INF:  Started simulation on 'Content/Talos/Levels/Cloud_1_01.wld' \
+                    in 0.54 seconds.";
+        assert_eq!(parse_line(line),
+                   Some(LogEvent::SimulationStarted("Content/Talos/Levels/Cloud_1_01.wld"
+                                                        .to_string())));
+    }
+
+    #[test]
+    fn parses_simulation_stop() {
+        assert_eq!(parse_line("12:00:01 INF:  Stopping simulation (duration: 1234.56)"),
+                   Some(LogEvent::SimulationStopped));
+    }
+
+    #[test]
+    fn parses_user_commands() {
+        assert_eq!(parse_line("12:00:01 INF:  USER: /transcend  "),
+                   Some(LogEvent::UserCommand("/transcend".to_string())));
+    }
+
+    #[test]
+    fn parses_progress_saves() {
+        assert_eq!(parse_line("12:00:01 INF:  Save Talos Progress: delayed request"),
+                   Some(LogEvent::ProgressSaved));
+    }
+
+    #[test]
+    fn ignores_other_lines() {
+        assert_eq!(parse_line("12:00:01 INF:  Loading world"), None);
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("12:34:56 INF:  Picked: DJ3"),
+                   Some((NaiveTime::from_hms(12, 34, 56), Duration::seconds(1))));
+        assert_eq!(parse_timestamp("01:02:03.45 INF:  Picked: DJ3"),
+                   Some((NaiveTime::from_hms_milli(1, 2, 3, 450), Duration::milliseconds(10))));
+    }
+
+    #[test]
+    fn rejects_malformed_timestamps() {
+        assert_eq!(parse_timestamp("25:00:00 INF:  Picked: DJ3"), None);
+        assert_eq!(parse_timestamp("12:3:45 INF:  Picked: DJ3"), None);
+        assert_eq!(parse_timestamp("INF:  Picked: DJ3"), None);
+    }
+}
@@ -29,21 +29,44 @@ mod errors {
 
 mod config;
 mod game_time;
+mod game_versions;
+mod log_file;
+mod learn;
+mod log_parser;
+mod memory;
+mod names;
+mod presets;
 mod process_list;
+mod replay;
+mod route;
+mod rules;
 mod timer;
+mod worlds;
 
 #[cfg(all(not(windows), not(target_os = "macos")))]
 mod center_mouse;
 
 fn usage() {
-    println!("Usage: {} <path/to/Talos.log> <path/to/splits.lss>",
+    println!("Usage: {} <path/to/Talos.log> <path/to/splits.lss> \
+              [--replay | --il <world> | --learn]",
              env::args().nth(0).unwrap());
 }
 
+fn run_replay() {
+    if let Err(ref e) = replay::run() {
+        println!("{}", e.display());
+        usage();
+    }
+}
+
 #[cfg(all(not(windows), not(target_os = "macos")))]
 fn main() {
     use std::thread;
 
+    if timer::replay_requested() {
+        return run_replay();
+    }
+
     let center_mouse_thread = thread::spawn(center_mouse::run);
 
     if let Err(ref e) = timer::run() {
@@ -57,6 +80,10 @@ fn main() {
 
 #[cfg(not(all(not(windows), not(target_os = "macos"))))]
 fn main() {
+    if timer::replay_requested() {
+        return run_replay();
+    }
+
     if let Err(ref e) = timer::run() {
         println!("{}", e.display());
         usage();
@@ -0,0 +1,435 @@
+use std::cmp::min;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use read_process_memory::{CopyAddress, Pid, ProcessHandle};
+use serde::{de, Deserialize, Deserializer};
+
+use errors::*;
+
+/// A path to a typed value in the game memory, written as
+/// `<base>[, <offset>...] : <type> [& <mask>]`, for example `Talos+0x2C82BA0, 0x58 : u8 & 1`.
+///
+/// The base is an address, optionally relative to a loaded module (the executable or a library,
+/// by file name). Every offset is added to the pointer read at the previous address. The types are
+/// `u8`, `u16`, `u32`, `u64`, `i8`, `i16`, `i32`, `i64`, `f32`, `f64`, `pointer` and `string`,
+/// optionally with the maximum length in bytes, as in `string[32]`. Integers can be masked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValuePath {
+    base: Base,
+    offsets: Vec<i64>,
+    value_type: ValueType,
+    mask: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Base {
+    Address(u64),
+    Module(String, u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueType {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Pointer,
+    String(usize),
+}
+
+/// A value read from the game memory.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    Pointer(u64),
+    String(String),
+}
+
+/// A change of the value of a watcher between two reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatcherChange {
+    pub name: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+impl WatcherChange {
+    /// Returns whether the value is a number that grew.
+    pub fn increased(&self) -> bool {
+        match (&self.old, &self.new) {
+            (&Value::Integer(old), &Value::Integer(new)) => new > old,
+            (&Value::Float(old), &Value::Float(new)) => new > old,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for WatcherChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} -> {}", self.name, self.old, self.new)
+    }
+}
+
+const DEFAULT_STRING_LENGTH: usize = 64;
+
+impl Value {
+    /// Returns whether the value is non-zero or non-empty.
+    pub fn is_set(&self) -> bool {
+        match *self {
+            Value::Integer(x) => x != 0,
+            Value::Float(x) => x != 0.0,
+            Value::Pointer(x) => x != 0,
+            Value::String(ref x) => !x.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Integer(x) => write!(f, "{}", x),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Pointer(x) => write!(f, "{:#x}", x),
+            Value::String(ref x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl ValuePath {
+    /// A path reading the lowest bit of the byte at the end of the pointer path.
+    pub fn flag(address: u64, offsets: &[u64]) -> Self {
+        Self {
+            base: Base::Address(address),
+            offsets: offsets.iter().map(|&x| x as i64).collect(),
+            value_type: ValueType::U8,
+            mask: Some(1),
+        }
+    }
+
+    /// Replaces a module-relative base with the address in the process.
+    pub fn resolve(&self, module_bases: &HashMap<String, u64>) -> Result<Self> {
+        let base = match self.base {
+            Base::Address(address) => address,
+            Base::Module(ref name, offset) => {
+                match module_bases.get(name) {
+                    Some(&base) => base + offset,
+                    None => bail!("module {} is not loaded", name),
+                }
+            }
+        };
+
+        Ok(Self {
+               base: Base::Address(base),
+               ..self.clone()
+           })
+    }
+
+    /// Follows the path and reads the value at its end. The path must have been resolved.
+    pub fn read(&self, process: &ProcessHandle) -> Option<Value> {
+        let mut address = match self.base {
+            Base::Address(address) => address,
+            Base::Module(..) => return None,
+        };
+
+        for &offset in &self.offsets {
+            address = match read_integer(process, address, 8) {
+                Some(pointer) => pointer.wrapping_add(offset as u64),
+                None => return None,
+            };
+        }
+
+        let size = match self.value_type {
+            ValueType::U8 | ValueType::I8 => 1,
+            ValueType::U16 | ValueType::I16 => 2,
+            ValueType::U32 | ValueType::I32 | ValueType::F32 => 4,
+            ValueType::U64 | ValueType::I64 | ValueType::F64 | ValueType::Pointer => 8,
+            ValueType::String(length) => {
+                return read_string(process, address, length).map(Value::String)
+            }
+        };
+
+        let raw = match read_integer(process, address, size) {
+            Some(x) => x & self.mask.unwrap_or(!0),
+            None => return None,
+        };
+
+        let value = match self.value_type {
+            ValueType::I8 => Value::Integer(raw as u8 as i8 as i64),
+            ValueType::I16 => Value::Integer(raw as u16 as i16 as i64),
+            ValueType::I32 => Value::Integer(raw as u32 as i32 as i64),
+            ValueType::F32 => Value::Float(f32::from_bits(raw as u32) as f64),
+            ValueType::F64 => Value::Float(f64::from_bits(raw)),
+            ValueType::Pointer => Value::Pointer(raw),
+            _ => Value::Integer(raw as i64),
+        };
+
+        Some(value)
+    }
+}
+
+/// Reads a little-endian unsigned integer of `size` bytes.
+fn read_integer(process: &ProcessHandle, address: u64, size: usize) -> Option<u64> {
+    let mut buf = [0u8; 8];
+    if process.copy_address(address as usize, &mut buf[..size]).is_err() {
+        return None;
+    }
+
+    Some(buf[..size].iter()
+                    .rev()
+                    .fold(0, |value, &byte| value << 8 | byte as u64))
+}
+
+/// Reads a NUL-terminated string of at most `length` bytes.
+fn read_string(process: &ProcessHandle, address: u64, length: usize) -> Option<String> {
+    let mut buf = vec![0u8; length];
+    if process.copy_address(address as usize, &mut buf).is_err() {
+        return None;
+    }
+
+    let end = buf.iter().position(|&x| x == 0).unwrap_or(length);
+    Some(String::from_utf8_lossy(&buf[..end]).into_owned())
+}
+
+impl FromStr for ValuePath {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        let (path, value) = match string.find(':') {
+            Some(index) => (&string[..index], &string[index + 1..]),
+            None => bail!("missing the value type in {:?}", string),
+        };
+
+        let (value_type, mask) = match value.find('&') {
+            Some(index) => (&value[..index], Some(parse_number(value[index + 1..].trim())?)),
+            None => (value, None),
+        };
+        let value_type = parse_value_type(value_type.trim())?;
+        if mask.is_some() {
+            match value_type {
+                ValueType::F32 | ValueType::F64 | ValueType::String(_) => {
+                    bail!("only integers can be masked in {:?}", string)
+                }
+                _ => {}
+            }
+        }
+
+        let mut parts = path.split(',');
+        let base = parse_base(parts.next().unwrap_or("").trim())?;
+        let offsets = parts.map(|x| parse_offset(x.trim()))
+                           .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+               base,
+               offsets,
+               value_type,
+               mask,
+           })
+    }
+}
+
+impl<'de> Deserialize<'de> for ValuePath {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let string = String::deserialize(deserializer)?;
+        string.parse()
+              .map_err(|e: Error| de::Error::custom(e.to_string()))
+    }
+}
+
+fn parse_number(string: &str) -> Result<u64> {
+    let result = if string.starts_with("0x") || string.starts_with("0X") {
+        u64::from_str_radix(&string[2..], 16)
+    } else {
+        string.parse()
+    };
+
+    result.chain_err(|| format!("invalid number {:?}", string))
+}
+
+fn parse_offset(string: &str) -> Result<i64> {
+    if string.starts_with('-') {
+        Ok(-(parse_number(string[1..].trim())? as i64))
+    } else {
+        Ok(parse_number(string)? as i64)
+    }
+}
+
+fn parse_base(string: &str) -> Result<Base> {
+    if string.is_empty() {
+        bail!("missing the base address");
+    }
+
+    // Module names can contain pluses themselves, as in `libstdc++.so.6+0x10`.
+    match string.rfind('+') {
+        Some(index) => {
+            Ok(Base::Module(string[..index].trim().to_string(),
+                            parse_number(string[index + 1..].trim())?))
+        }
+        // Anything starting with a digit is meant as an address, so a typo in it is an error.
+        None if string.starts_with(|c: char| c.is_digit(10)) => {
+            Ok(Base::Address(parse_number(string)?))
+        }
+        None => Ok(Base::Module(string.to_string(), 0)),
+    }
+}
+
+fn parse_value_type(string: &str) -> Result<ValueType> {
+    let value_type = match string {
+        "u8" => ValueType::U8,
+        "u16" => ValueType::U16,
+        "u32" => ValueType::U32,
+        "u64" => ValueType::U64,
+        "i8" => ValueType::I8,
+        "i16" => ValueType::I16,
+        "i32" => ValueType::I32,
+        "i64" => ValueType::I64,
+        "f32" => ValueType::F32,
+        "f64" => ValueType::F64,
+        "pointer" => ValueType::Pointer,
+        "string" => ValueType::String(DEFAULT_STRING_LENGTH),
+        _ if string.starts_with("string[") && string.ends_with(']') => {
+            ValueType::String(parse_number(&string[7..string.len() - 1])? as usize)
+        }
+        _ => bail!("unknown value type {:?}", string),
+    };
+
+    Ok(value_type)
+}
+
+/// A memory region of a process, from `/proc/<pid>/maps`.
+struct MemoryRegion {
+    start: u64,
+    end: u64,
+    readable: bool,
+    path: String,
+}
+
+fn memory_regions(pid: Pid) -> Result<Vec<MemoryRegion>> {
+    let mut maps = String::new();
+    File::open(format!("/proc/{}/maps", pid))
+        .and_then(|mut x| x.read_to_string(&mut maps))
+        .chain_err(|| "could not read the Talos memory map")?;
+
+    let mut regions = Vec::new();
+    for line in maps.lines() {
+        // address perms offset dev inode pathname
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.len() < 5 {
+            continue;
+        }
+
+        let mut range = fields[0].split('-')
+                                 .map(|x| u64::from_str_radix(x, 16));
+        if let (Some(Ok(start)), Some(Ok(end))) = (range.next(), range.next()) {
+            regions.push(MemoryRegion {
+                             start,
+                             end,
+                             readable: fields[1].starts_with('r'),
+                             path: fields[5..].join(" "),
+                         });
+        }
+    }
+
+    Ok(regions)
+}
+
+/// Returns the readable memory regions mapped from the executable of the process.
+pub fn executable_regions(pid: Pid) -> Result<Vec<(u64, u64)>> {
+    let executable = fs::read_link(format!("/proc/{}/exe", pid))
+        .chain_err(|| "could not find the Talos executable")?;
+
+    Ok(memory_regions(pid)?.into_iter()
+                           .filter(|x| x.readable && Path::new(&x.path) == executable)
+                           .map(|x| (x.start, x.end))
+                           .collect())
+}
+
+/// Returns the base addresses of the modules loaded in the process, by file name.
+pub fn module_bases(pid: Pid) -> Result<HashMap<String, u64>> {
+    let mut bases = HashMap::new();
+    for region in memory_regions(pid)? {
+        let name = match Path::new(&region.path).file_name().and_then(|x| x.to_str()) {
+            Some(name) if region.path.starts_with('/') => name.to_string(),
+            _ => continue,
+        };
+
+        let base = bases.entry(name).or_insert(region.start);
+        *base = min(*base, region.start);
+    }
+
+    Ok(bases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_module_relative_paths() {
+        assert_eq!("Talos+0x2C82BA0, 0x58 : u8 & 1".parse::<ValuePath>().unwrap(),
+                   ValuePath {
+                       base: Base::Module("Talos".to_string(), 0x2C82BA0),
+                       offsets: vec![0x58],
+                       value_type: ValueType::U8,
+                       mask: Some(1),
+                   });
+    }
+
+    #[test]
+    fn parses_module_names_with_pluses() {
+        assert_eq!("libstdc++.so.6+0x10 : pointer".parse::<ValuePath>().unwrap(),
+                   ValuePath {
+                       base: Base::Module("libstdc++.so.6".to_string(), 0x10),
+                       offsets: vec![],
+                       value_type: ValueType::Pointer,
+                       mask: None,
+                   });
+    }
+
+    #[test]
+    fn parses_negative_offsets() {
+        assert_eq!("0x1000, -0x8, 16 : i32".parse::<ValuePath>().unwrap(),
+                   ValuePath {
+                       base: Base::Address(0x1000),
+                       offsets: vec![-8, 16],
+                       value_type: ValueType::I32,
+                       mask: None,
+                   });
+    }
+
+    #[test]
+    fn parses_string_lengths() {
+        assert_eq!("Talos+0x10, 0x20 : string[32]".parse::<ValuePath>().unwrap().value_type,
+                   ValueType::String(32));
+        assert_eq!("Talos+0x10 : string".parse::<ValuePath>().unwrap().value_type,
+                   ValueType::String(DEFAULT_STRING_LENGTH));
+    }
+
+    #[test]
+    fn rejects_masked_floats() {
+        assert!("Talos+0x10 : f32 & 1".parse::<ValuePath>().is_err());
+    }
+
+    #[test]
+    fn rejects_missing_types() {
+        assert!("Talos+0x10, 0x58".parse::<ValuePath>().is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        assert!("0x2C82BAG, 0x58 : u8".parse::<ValuePath>().is_err());
+    }
+}
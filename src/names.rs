@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+
+use serde_yaml;
+
+use errors::*;
+use log_parser::LogEvent;
+use worlds::WORLDS;
+
+/// Friendly names for the internal names used in the Talos log.
+#[derive(Debug, Default)]
+pub struct FriendlyNames {
+    puzzles: HashMap<String, String>,
+    /// World file to friendly name.
+    worlds: HashMap<String, String>,
+    /// Lowercase friendly name to world file.
+    world_files: HashMap<String, String>,
+}
+
+impl FriendlyNames {
+    /// Loads the puzzle names from a YAML file mapping internal names to friendly ones, and the
+    /// built-in world names along with the extra ones, given as friendly name to world file.
+    ///
+    /// A missing puzzle names file is not an error.
+    pub fn load(puzzle_names_filename: &str,
+                extra_worlds: &HashMap<String, String>)
+                -> Result<Self> {
+        let puzzles = match File::open(puzzle_names_filename) {
+            Ok(file) => {
+                serde_yaml::from_reader(file)
+                    .chain_err(|| format!("could not parse {}", puzzle_names_filename))?
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                return Err(e).chain_err(|| format!("could not open {}", puzzle_names_filename))
+            }
+        };
+
+        let mut names = Self {
+            puzzles,
+            worlds: HashMap::new(),
+            world_files: HashMap::new(),
+        };
+
+        for &(name, file) in WORLDS {
+            names.add_world(name, file);
+        }
+        for (name, file) in extra_worlds {
+            names.add_world(name, file);
+        }
+
+        Ok(names)
+    }
+
+    fn add_world(&mut self, name: &str, file: &str) {
+        self.worlds.insert(file.to_string(), name.to_string());
+        self.world_files.insert(name.to_lowercase(), file.to_string());
+    }
+
+    pub fn world<'a>(&'a self, file: &'a str) -> &'a str {
+        self.worlds.get(file).map(|x| x.as_str()).unwrap_or(file)
+    }
+
+    /// Returns the world file for a friendly world name, or the argument if it's not one.
+    pub fn world_file(&self, name: &str) -> String {
+        self.world_files
+            .get(&name.to_lowercase())
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    pub fn puzzle<'a>(&'a self, name: &'a str) -> &'a str {
+        self.puzzles.get(name).map(|x| x.as_str()).unwrap_or(name)
+    }
+
+    /// Returns the internal puzzle names matching the given internal or friendly names.
+    pub fn internal_puzzle_names(&self, names: &[String]) -> Vec<String> {
+        let mut rv = names.to_vec();
+        rv.extend(self.puzzles
+                      .iter()
+                      .filter(|&(_, friendly)| names.contains(friendly))
+                      .map(|(internal, _)| internal.clone()));
+        rv
+    }
+
+    /// Returns a name for the segment ended by the event: the world that was left, the puzzle or
+    /// the item.
+    pub fn segment_name(&self, event: &LogEvent, left_world: Option<&str>) -> String {
+        match *event {
+            LogEvent::WorldChanged(ref world) => {
+                self.world(left_world.unwrap_or(world)).to_string()
+            }
+            LogEvent::PuzzleSolved(ref name) => self.puzzle(name).to_string(),
+            LogEvent::ItemPicked(ref name) => name.clone(),
+            _ => self.describe(event),
+        }
+    }
+
+    /// Returns a human-readable description of the event.
+    pub fn describe(&self, event: &LogEvent) -> String {
+        match *event {
+            LogEvent::WorldChanged(ref world) => format!("entered {}", self.world(world)),
+            LogEvent::PuzzleSolved(ref name) => format!("solved {}", self.puzzle(name)),
+            LogEvent::ItemPicked(ref name) => format!("picked {}", name),
+            LogEvent::SimulationStarted(ref world) => format!("started {}", self.world(world)),
+            LogEvent::SimulationStopped => "stopped the simulation".to_string(),
+            LogEvent::UserCommand(ref command) => format!("entered {:?}", command),
+            LogEvent::ProgressSaved => "saved the progress".to_string(),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use config::{GehennaConfig, StoppingSimulation};
+use errors::*;
+use log_parser::LogEvent;
+use rules::{Action, Conditions, EventKind, Rule, Trigger};
+
+pub const A1: &str = "Content/Talos/Levels/Cloud_1_01.wld";
+pub const NEXUS: &str = "Content/Talos/Levels/Nexus.wld";
+
+// The Road to Gehenna world files aren't built in, these names have to be set in `world_names`.
+pub const GEHENNA_INTRO: &str = "Gehenna Intro";
+pub const GEHENNA_HUB: &str = "Gehenna Hub";
+pub const GEHENNA_AREAS: [&str; 4] = ["Gehenna 1", "Gehenna 2", "Gehenna 3", "Gehenna 4"];
+pub const GEHENNA_ENDING: &str = "Gehenna Ending";
+
+/// Game endings that can finish a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ending {
+    Transcendence,
+    Eternalization,
+    Messenger,
+    /// The hidden ending of Road to Gehenna.
+    Hidden,
+    /// The regular ending of Road to Gehenna.
+    Gehenna,
+}
+
+impl Ending {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Ending::Transcendence => "transcendence",
+            Ending::Eternalization => "eternalization",
+            Ending::Messenger => "messenger",
+            Ending::Hidden => "hidden",
+            Ending::Gehenna => "gehenna",
+        }
+    }
+
+    /// Returns the default rule finishing the run on this ending.
+    ///
+    /// The messenger and hidden endings have no log line of their own that tells them apart from
+    /// other input in their worlds, so they need a trigger in the config.
+    pub fn rule(&self) -> Option<Rule> {
+        match *self {
+            Ending::Transcendence => {
+                Some(Rule::new(Trigger::regex(r"USER: /transcend\b"), Action::Finish)
+                         .in_world(NEXUS))
+            }
+            Ending::Eternalization => {
+                Some(Rule::new(Trigger::regex(r"USER: /eternalize\b"), Action::Finish)
+                         .in_world(NEXUS))
+            }
+            Ending::Messenger | Ending::Hidden => None,
+            Ending::Gehenna => {
+                Some(Rule::new(Trigger::event_with_value(EventKind::WorldChanged, GEHENNA_ENDING),
+                               Action::Finish))
+            }
+        }
+    }
+}
+
+/// A replacement trigger for an ending, set in the config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EndingTrigger {
+    pub trigger: Trigger,
+    #[serde(default)]
+    pub conditions: Conditions,
+}
+
+/// Built-in categories with their start, split and end triggers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    AnyPercent,
+    AllSigils,
+    Gehenna,
+    TrueEnding,
+}
+
+impl Category {
+    /// Looks up a category by name, ignoring case, spaces and punctuation.
+    ///
+    /// This works both with the `category` config key and with `.lss` category names.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.chars()
+                       .filter(|c| c.is_alphanumeric() || *c == '%')
+                       .flat_map(|c| c.to_lowercase())
+                       .collect::<String>();
+
+        match name.as_str() {
+            "any%" | "anypercent" => Some(Category::AnyPercent),
+            "allsigils" => Some(Category::AllSigils),
+            "gehenna" | "roadtogehenna" => Some(Category::Gehenna),
+            "trueending" | "transcendence" => Some(Category::TrueEnding),
+            _ => None,
+        }
+    }
+
+    /// Selects the preset for the `category` config key.
+    ///
+    /// Without the key no preset is used, so the `split_on_*` options apply. With `auto`, the
+    /// preset is chosen by the splits file category name if one matches it.
+    pub fn select(name: Option<&str>, splits_category_name: &str) -> Result<Option<Self>> {
+        match name {
+            None => Ok(None),
+            Some(name) if name.eq_ignore_ascii_case("auto") => {
+                Ok(Self::from_name(splits_category_name))
+            }
+            Some(name) => {
+                Self::from_name(name).map(Some)
+                                     .ok_or_else(|| format!("unknown category {:?}", name).into())
+            }
+        }
+    }
+
+    /// Returns the start and split rules of the category.
+    pub fn rules(&self) -> Vec<Rule> {
+        let mut rules = Vec::new();
+
+        match *self {
+            Category::AnyPercent | Category::TrueEnding => {
+                rules.push(start_on(A1));
+                rules.push(split_on_return_to_nexus());
+            }
+            Category::AllSigils => {
+                rules.push(start_on(A1));
+                rules.push(split_on_return_to_nexus());
+                rules.push(split_on_sigils());
+                rules.push(split_on_puzzles());
+            }
+            Category::Gehenna => {
+                rules.extend(gehenna_rules(&GehennaConfig {
+                                                start: true,
+                                                split_on_area_completion: true,
+                                                split_on_ending: true,
+                                            }));
+            }
+        }
+
+        rules
+    }
+
+    /// Returns the endings which finish a run of the category.
+    pub fn endings(&self) -> Vec<Ending> {
+        match *self {
+            Category::AnyPercent | Category::AllSigils => {
+                vec![Ending::Transcendence, Ending::Eternalization, Ending::Messenger]
+            }
+            Category::Gehenna => vec![Ending::Gehenna],
+            Category::TrueEnding => vec![Ending::Transcendence],
+        }
+    }
+}
+
+pub fn start_on(world: &str) -> Rule {
+    Rule::new(Trigger::event_with_value(EventKind::SimulationStarted, world),
+              Action::Start)
+}
+
+/// Rules for handling the simulation being stopped, for example by quitting to the menu.
+pub fn stopping_simulation_rules(behaviour: StoppingSimulation) -> Vec<Rule> {
+    let stopped = Trigger::event(EventKind::SimulationStopped);
+
+    match behaviour {
+        StoppingSimulation::Reset => vec![Rule::new(stopped, Action::Reset)],
+        StoppingSimulation::ResetWithoutGolds => {
+            vec![Rule::new(stopped, Action::ResetWithoutGolds)]
+        }
+        StoppingSimulation::Pause => {
+            vec![Rule::new(stopped, Action::Pause),
+                 Rule::new(Trigger::event(EventKind::SimulationStarted), Action::Resume)]
+        }
+        StoppingSimulation::Ignore => Vec::new(),
+    }
+}
+
+pub fn split_on_return_to_nexus() -> Rule {
+    split_on_entering(NEXUS, None)
+}
+
+/// Splits on entering the world, optionally only when coming from `from`.
+pub fn split_on_entering(world: &str, from: Option<&str>) -> Rule {
+    let rule = Rule::new(Trigger::event_with_value(EventKind::WorldChanged, world),
+                         Action::Split);
+
+    match from {
+        Some(from) => rule.coming_from(from),
+        None => rule,
+    }
+}
+
+pub fn split_on_sigils() -> Rule {
+    Rule::new(Trigger::event(EventKind::ItemPicked), Action::Split).deduplicated()
+}
+
+pub fn split_on_puzzles() -> Rule {
+    Rule::new(Trigger::event(EventKind::PuzzleSolved), Action::Split).deduplicated()
+}
+
+pub fn gehenna_rules(config: &GehennaConfig) -> Vec<Rule> {
+    let mut rules = Vec::new();
+
+    if config.start {
+        rules.push(start_on(GEHENNA_INTRO));
+    }
+
+    if config.split_on_area_completion {
+        for area in &GEHENNA_AREAS {
+            rules.push(Rule::new(Trigger::event_with_value(EventKind::WorldChanged, GEHENNA_HUB),
+                                 Action::Split)
+                           .coming_from(area));
+        }
+    }
+
+    rules
+}
+
+/// Console commands for controlling the timer from the game, along with their actions.
+///
+/// They all start with "/tt_", so they never match the ending triggers.
+pub const CONSOLE_COMMANDS: &[(&str, Action)] = &[("tt_start", Action::Start),
+                                                  ("tt_split", Action::Split),
+                                                  ("tt_skip", Action::Skip),
+                                                  ("tt_undo", Action::Undo),
+                                                  ("tt_reset", Action::Reset),
+                                                  ("tt_pause", Action::Pause),
+                                                  ("tt_resume", Action::Resume)];
+
+/// Returns whether the event is one of the console commands for controlling the timer.
+pub fn is_console_command(event: &LogEvent) -> bool {
+    match *event {
+        LogEvent::UserCommand(ref command) => command.starts_with("/tt_"),
+        _ => false,
+    }
+}
+
+pub fn console_command_rules() -> Vec<Rule> {
+    CONSOLE_COMMANDS.iter()
+                    .map(|&(command, action)| {
+                             Rule::new(Trigger::regex(&format!(r"USER:\s*/{}\s*$", command)),
+                                       action)
+                         })
+                    .collect()
+}
+
+/// Rules for timing a single world: the timer starts on entering it from the Nexus and stops on
+/// picking up the sigil or on returning to the Nexus.
+pub fn individual_level_rules(world: &str) -> Vec<Rule> {
+    vec![// Entering the world again starts a new attempt.
+         Rule::new(Trigger::event_with_value(EventKind::WorldChanged, world),
+                   Action::Reset)
+             .coming_from(NEXUS),
+         Rule::new(Trigger::event_with_value(EventKind::WorldChanged, world),
+                   Action::Start)
+             .coming_from(NEXUS),
+         Rule::new(Trigger::event(EventKind::ItemPicked), Action::Split)
+             .in_world(world)
+             .deduplicated(),
+         Rule::new(Trigger::event_with_value(EventKind::WorldChanged, NEXUS),
+                   Action::Split)
+             .coming_from(world)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uses_the_options_without_a_category() {
+        assert_eq!(Category::select(None, "Any%").unwrap(), None);
+    }
+
+    #[test]
+    fn matches_the_splits_category_name_only_with_auto() {
+        assert_eq!(Category::select(Some("auto"), "Any%").unwrap(), Some(Category::AnyPercent));
+        assert_eq!(Category::select(Some("auto"), "Sigils in B").unwrap(), None);
+    }
+
+    #[test]
+    fn prefers_the_configured_category() {
+        assert_eq!(Category::select(Some("Road to Gehenna"), "Any%").unwrap(),
+                   Some(Category::Gehenna));
+        assert!(Category::select(Some("Any"), "Any%").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use errors::*;
+use config::*;
+use game_time::WatcherValues;
+use timer::{check_route, create_timer, process_line, splits_filename, ArgumentPosition,
+            GameState, ProcessedLine};
+
+/// Feeds an existing Talos log through the autosplitter and prints every action it fires.
+pub fn run() -> Result<()> {
+    let timer = create_timer()?;
+    let mut config = read_config().chain_err(|| "failed to read config")?;
+    config.load_route(&splits_filename()?)
+          .chain_err(|| "failed to read the route")?;
+    config.select_rules(timer.run().category_name())
+          .chain_err(|| "failed to read config")?;
+    check_route(&config, &timer)?;
+    // The log was written long ago.
+    config.log_timestamps = false;
+
+    for warning in &config.warnings {
+        println!("warning: {}", warning);
+    }
+
+    let log_filename = env::args().nth(ArgumentPosition::TalosLogFilename as usize)
+                                  .ok_or("the log filename argument is missing")?;
+    let log = File::open(&log_filename).chain_err(|| "could not open the Talos log file")?;
+
+    let timer = timer.into_shared();
+    let mut state = GameState::new(WatcherValues::default());
+
+    for (index, line) in BufReader::new(log).lines().enumerate() {
+        let line = line.chain_err(|| "error reading the Talos log file")?;
+
+        let previous_world = state.current_world.clone();
+        let ProcessedLine {
+            event,
+            actions,
+            warning,
+            ..
+        } = process_line(&config, &timer, &mut state, &line)?;
+        if let Some(warning) = warning {
+            println!("line {:>6}: warning: {}", index + 1, warning);
+        }
+
+        for action in actions {
+            let world = state.current_world
+                             .as_ref()
+                             .or(previous_world.as_ref())
+                             .map(|x| config.names.world(x))
+                             .unwrap_or("<no world>");
+            let description = event.as_ref()
+                                   .map(|x| config.names.describe(x))
+                                   .unwrap_or_else(|| line.trim().to_string());
+
+            println!("line {:>6}: {:<5} in {} ({})",
+                     index + 1,
+                     action.name(),
+                     world,
+                     description);
+        }
+    }
+
+    Ok(())
+}
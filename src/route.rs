@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use serde_yaml;
+
+use errors::*;
+use rules::{Action, Conditions, Context, Rule, Trigger};
+
+/// The event expected to end a segment.
+#[derive(Debug, Clone, Deserialize)]
+struct RouteStep {
+    trigger: Trigger,
+    #[serde(default)]
+    conditions: Conditions,
+}
+
+/// The expected events of every segment, in order.
+#[derive(Debug, Default)]
+pub struct Route {
+    steps: Vec<Rule>,
+}
+
+/// How an event compares to the route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteCheck {
+    /// The event is the expected one.
+    Expected,
+    /// The event is expected for a later segment.
+    Ahead(usize),
+    /// The event is not expected by any of the remaining segments.
+    OffRoute,
+}
+
+impl Route {
+    /// Loads the route from a YAML file with a list of triggers and optional conditions, one per
+    /// segment.
+    ///
+    /// Returns `None` if the file doesn't exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).chain_err(|| format!("could not open {}", path.display()))
+            }
+        };
+
+        let steps: Vec<RouteStep> = serde_yaml::from_reader(file)
+            .chain_err(|| format!("could not parse {}", path.display()))?;
+
+        Ok(Some(Self {
+                    steps: steps.into_iter()
+                                .map(|x| {
+                                         let mut rule = Rule::new(x.trigger, Action::Split);
+                                         rule.conditions = x.conditions;
+                                         rule
+                                     })
+                                .collect(),
+                }))
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Replaces world names and compiles the steps, like for the rules.
+    pub fn compile<F: Fn(&str) -> String>(&mut self, f: F) -> Result<()> {
+        for (i, step) in self.steps.iter_mut().enumerate() {
+            step.map_worlds(&f);
+            step.compile()
+                .chain_err(|| format!("invalid route step #{}", i + 1))?;
+        }
+
+        Ok(())
+    }
+
+    /// Compares the event with the step of the given segment and the ones after it.
+    ///
+    /// A `repeated` sigil or puzzle, seen before in the attempt (for example after a checkpoint
+    /// reload), is off the route.
+    pub fn check(&self, segment: usize, context: &Context, repeated: bool) -> RouteCheck {
+        if repeated {
+            return RouteCheck::OffRoute;
+        }
+
+        match self.steps
+                  .iter()
+                  .skip(segment)
+                  .position(|x| x.matches(context)) {
+            Some(0) => RouteCheck::Expected,
+            Some(x) => RouteCheck::Ahead(x),
+            None => RouteCheck::OffRoute,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log_parser::LogEvent;
+    use rules::{evaluate, EventKind, RuleState};
+
+    const A1: &str = "Content/Talos/Levels/Cloud_1_01.wld";
+
+    /// Checks picking up the item against the route and remembers it like the rules do.
+    fn pick(route: &Route, state: &mut RuleState, segment: usize, item: &str) -> RouteCheck {
+        let line = format!("12:00:00 INF:  Picked: {}", item);
+        let event = LogEvent::ItemPicked(item.to_string());
+        let context = Context {
+            line: &line,
+            event: Some(&event),
+            world_changed: false,
+            world: Some(A1),
+            previous_world: None,
+            change: None,
+        };
+
+        let repeated = state.is_repeated(&context);
+        evaluate(&[], state, &context);
+        route.check(segment, &context, repeated)
+    }
+
+    #[test]
+    fn repeated_items_are_off_route() {
+        let step = Rule::new(Trigger::event(EventKind::ItemPicked), Action::Split).in_world(A1);
+        let route = Route { steps: vec![step.clone(), step] };
+        let mut state = RuleState::new();
+
+        assert_eq!(pick(&route, &mut state, 0, "DJ1"), RouteCheck::Expected);
+        // Picked up again after a checkpoint reload.
+        assert_eq!(pick(&route, &mut state, 1, "DJ1"), RouteCheck::OffRoute);
+        assert_eq!(pick(&route, &mut state, 1, "DJ2"), RouteCheck::Expected);
+    }
+}
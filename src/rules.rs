@@ -0,0 +1,645 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use errors::*;
+use log_parser::LogEvent;
+use memory::WatcherChange;
+
+/// How long a rule firing on one source waits for the duplicate event from the other source.
+const DUPLICATE_WINDOW_SECONDS: u64 = 10;
+
+/// Timer actions fired by the autosplitter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Start,
+    Split,
+    /// Skips to the last split and splits, ending the run.
+    Finish,
+    Skip,
+    Undo,
+    Reset,
+    ResetWithoutGolds,
+    Pause,
+    Resume,
+    PauseGameTime,
+    ResumeGameTime,
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Action::Start => "start",
+            Action::Split => "split",
+            Action::Finish => "finish",
+            Action::Skip => "skip",
+            Action::Undo => "undo",
+            Action::Reset => "reset",
+            Action::ResetWithoutGolds => "reset without golds",
+            Action::Pause => "pause",
+            Action::Resume => "resume",
+            Action::PauseGameTime => "pause game time",
+            Action::ResumeGameTime => "resume game time",
+        }
+    }
+}
+
+/// Kinds of parsed log events that can trigger a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    WorldChanged,
+    PuzzleSolved,
+    ItemPicked,
+    SimulationStarted,
+    SimulationStopped,
+    UserCommand,
+    ProgressSaved,
+}
+
+impl EventKind {
+    /// Returns the kind of the event along with its argument, if it has one.
+    fn of(event: &LogEvent) -> (Self, Option<&str>) {
+        match *event {
+            LogEvent::WorldChanged(ref x) => (EventKind::WorldChanged, Some(x.as_str())),
+            LogEvent::PuzzleSolved(ref x) => (EventKind::PuzzleSolved, Some(x.as_str())),
+            LogEvent::ItemPicked(ref x) => (EventKind::ItemPicked, Some(x.as_str())),
+            LogEvent::SimulationStarted(ref x) => (EventKind::SimulationStarted, Some(x.as_str())),
+            LogEvent::SimulationStopped => (EventKind::SimulationStopped, None),
+            LogEvent::UserCommand(ref x) => (EventKind::UserCommand, Some(x.as_str())),
+            LogEvent::ProgressSaved => (EventKind::ProgressSaved, None),
+        }
+    }
+}
+
+/// What a rule reacts to: a parsed log event, a regex matched against the raw line or a change
+/// of a value read from the game memory.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Trigger {
+    pub event: Option<EventKind>,
+    /// If set, the event argument (world, puzzle, item or console text) or the new value of the
+    /// watcher must be equal to this.
+    pub value: Option<String>,
+    pub regex: Option<String>,
+    /// Name of the watcher (see the game versions file) whose value changing fires the trigger.
+    pub watcher: Option<String>,
+    /// Fire only when the value of the watcher is a number that grew.
+    pub increased: bool,
+    #[serde(skip)]
+    compiled_regex: Option<Regex>,
+}
+
+impl Trigger {
+    pub fn event(event: EventKind) -> Self {
+        Self {
+            event: Some(event),
+            ..Self::default()
+        }
+    }
+
+    pub fn event_with_value(event: EventKind, value: &str) -> Self {
+        Self {
+            event: Some(event),
+            value: Some(value.to_string()),
+            ..Self::default()
+        }
+    }
+
+    pub fn regex(regex: &str) -> Self {
+        Self {
+            regex: Some(regex.to_string()),
+            ..Self::default()
+        }
+    }
+
+    fn compile(&mut self) -> Result<()> {
+        match (self.event.is_some(), self.regex.as_ref(), self.watcher.is_some()) {
+            (true, None, false) | (false, None, true) => {}
+            (false, Some(regex), false) => {
+                if self.value.is_some() {
+                    bail!("a value can only be specified for event and watcher triggers");
+                }
+
+                self.compiled_regex =
+                    Some(Regex::new(regex).chain_err(|| format!("invalid regex {:?}", regex))?);
+            }
+            _ => bail!("a trigger must specify exactly one of an event, a regex or a watcher"),
+        }
+
+        if self.increased && self.watcher.is_none() {
+            bail!("increased can only be specified for watcher triggers");
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the world in the value of world events using the given function.
+    fn map_worlds<F: Fn(&str) -> String>(&mut self, f: F) {
+        if self.event == Some(EventKind::WorldChanged) ||
+            self.event == Some(EventKind::SimulationStarted)
+        {
+            self.value = self.value.as_ref().map(|x| f(x));
+        }
+    }
+
+    /// Returns whether the trigger reacts to the same kind of log event as `other`.
+    fn overlaps(&self, other: &Trigger) -> bool {
+        (self.event.is_some() && self.event == other.event) ||
+        (self.regex.is_some() && self.regex == other.regex)
+    }
+
+    fn matches(&self, context: &Context) -> bool {
+        if let Some(ref watcher) = self.watcher {
+            let change = match context.change {
+                Some(change) if change.name == *watcher => change,
+                _ => return false,
+            };
+
+            if self.increased && !change.increased() {
+                return false;
+            }
+
+            return match self.value {
+                       Some(ref value) => change.new.to_string() == *value,
+                       None => true,
+                   };
+        }
+
+        if let Some(ref regex) = self.compiled_regex {
+            // Memory changes have no log line.
+            return context.change.is_none() && regex.is_match(context.line);
+        }
+
+        let (kind, argument) = match context.event {
+            Some(event) => EventKind::of(event),
+            None => return false,
+        };
+
+        if self.event != Some(kind) {
+            return false;
+        }
+
+        // Changing over to the same world is not a world change.
+        if kind == EventKind::WorldChanged && !context.world_changed {
+            return false;
+        }
+
+        match self.value {
+            Some(ref value) => argument == Some(value.as_str()),
+            None => true,
+        }
+    }
+}
+
+/// Additional requirements for a triggered rule to fire.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Conditions {
+    /// The current world must be equal to this.
+    pub world: Option<String>,
+    /// The previous world must be equal to this.
+    pub previous_world: Option<String>,
+    /// If not empty, the current world must be one of these.
+    pub included_worlds: Vec<String>,
+    /// The current world must not be any of these.
+    pub excluded_worlds: Vec<String>,
+    /// If not empty, the event argument (for example, the sigil name) must be one of these.
+    pub included_values: Vec<String>,
+    /// The event argument must not be any of these.
+    pub excluded_values: Vec<String>,
+    /// Fire only on the given (1-based) occurrence of the trigger in the current attempt.
+    pub count: Option<u32>,
+}
+
+impl Conditions {
+    fn matches(&self, context: &Context) -> bool {
+        if self.world.is_some() && self.world.as_ref().map(|x| x.as_str()) != context.world {
+            return false;
+        }
+
+        if self.previous_world.is_some() &&
+            self.previous_world.as_ref().map(|x| x.as_str()) != context.previous_world
+        {
+            return false;
+        }
+
+        if !matches_lists(context.world, &self.included_worlds, &self.excluded_worlds) {
+            return false;
+        }
+
+        let new_value = context.change.map(|x| x.new.to_string());
+        let argument = context.event
+                              .and_then(|x| EventKind::of(x).1)
+                              .or_else(|| new_value.as_ref().map(|x| x.as_str()));
+        if !matches_lists(argument, &self.included_values, &self.excluded_values) {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Checks the value against an include list (ignored if empty) and an exclude list.
+fn matches_lists(value: Option<&str>, included: &[String], excluded: &[String]) -> bool {
+    match value {
+        Some(value) => {
+            (included.is_empty() || included.iter().any(|x| x == value)) &&
+            !excluded.iter().any(|x| x == value)
+        }
+        None => included.is_empty(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    pub trigger: Trigger,
+    #[serde(default)]
+    pub conditions: Conditions,
+    pub action: Action,
+    /// Ignore sigils and puzzles that were already picked up or solved in the current attempt,
+    /// for example after a checkpoint reload.
+    #[serde(default)]
+    pub deduplicate: bool,
+    /// For watcher rules, the log event reporting the same thing as the change of the watcher.
+    /// Whichever of the two comes first fires the action, and the action of the rule with the same
+    /// trigger as this one is then ignored.
+    #[serde(default)]
+    pub duplicates: Option<Trigger>,
+}
+
+impl Rule {
+    pub fn new(trigger: Trigger, action: Action) -> Self {
+        Self {
+            trigger,
+            conditions: Conditions::default(),
+            action,
+            deduplicate: false,
+            duplicates: None,
+        }
+    }
+
+    /// Makes the rule ignore repeated sigils and puzzles.
+    pub fn deduplicated(mut self) -> Self {
+        self.deduplicate = true;
+        self
+    }
+
+    /// Requires the current world to be equal to `world`.
+    pub fn in_world(mut self, world: &str) -> Self {
+        self.conditions.world = Some(world.to_string());
+        self
+    }
+
+    /// Requires the previous world to be equal to `world`.
+    pub fn coming_from(mut self, world: &str) -> Self {
+        self.conditions.previous_world = Some(world.to_string());
+        self
+    }
+
+    /// Requires the current world to be one of `worlds`, unless it's empty.
+    pub fn including_worlds(mut self, worlds: Vec<String>) -> Self {
+        self.conditions.included_worlds = worlds;
+        self
+    }
+
+    /// Requires the current world to not be any of `worlds`.
+    pub fn excluding_worlds(mut self, worlds: Vec<String>) -> Self {
+        self.conditions.excluded_worlds = worlds;
+        self
+    }
+
+    /// Requires the event argument to be one of `values`, unless it's empty.
+    pub fn including_values(mut self, values: Vec<String>) -> Self {
+        self.conditions.included_values = values;
+        self
+    }
+
+    /// Requires the event argument to not be any of `values`.
+    pub fn excluding_values(mut self, values: Vec<String>) -> Self {
+        self.conditions.excluded_values = values;
+        self
+    }
+
+    /// Replaces world names in the rule using the given function, for example to turn friendly
+    /// names into world files.
+    pub fn map_worlds<F: Fn(&str) -> String>(&mut self, f: F) {
+        self.trigger.map_worlds(&f);
+        if let Some(ref mut duplicates) = self.duplicates {
+            duplicates.map_worlds(&f);
+        }
+
+        let conditions = &mut self.conditions;
+        conditions.world = conditions.world.as_ref().map(|x| f(x));
+        conditions.previous_world = conditions.previous_world.as_ref().map(|x| f(x));
+        for world in conditions.included_worlds
+                               .iter_mut()
+                               .chain(conditions.excluded_worlds.iter_mut())
+        {
+            *world = f(world);
+        }
+    }
+
+    /// Returns whether the trigger and the conditions match, not counting occurrences.
+    pub fn matches(&self, context: &Context) -> bool {
+        self.trigger.matches(context) && self.conditions.matches(context)
+    }
+
+    /// Validates the rule and compiles its regex, if any.
+    pub fn compile(&mut self) -> Result<()> {
+        self.trigger.compile()?;
+
+        if let Some(ref mut duplicates) = self.duplicates {
+            if self.trigger.watcher.is_none() {
+                bail!("duplicates can only be specified for watcher triggers");
+            }
+            if duplicates.watcher.is_some() {
+                bail!("duplicates must be a log event or a regex");
+            }
+
+            duplicates.compile()
+                      .chain_err(|| "invalid duplicates trigger")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Everything the rules can look at when deciding whether to fire.
+pub struct Context<'a> {
+    pub line: &'a str,
+    pub event: Option<&'a LogEvent>,
+    /// Whether the event changed the current world.
+    pub world_changed: bool,
+    pub world: Option<&'a str>,
+    pub previous_world: Option<&'a str>,
+    /// The change of a watcher value, for memory events instead of log lines.
+    pub change: Option<&'a WatcherChange>,
+}
+
+/// Rule bookkeeping for the current attempt.
+#[derive(Debug, Default)]
+pub struct RuleState {
+    /// How many times each rule has been triggered.
+    counts: Vec<u32>,
+    /// Sigils and puzzles seen so far, as (world, name) pairs.
+    seen: HashSet<(Option<String>, String)>,
+    /// Rules with duplicate events that fired on one source and wait for the other one.
+    duplicates: Vec<PendingDuplicate>,
+}
+
+#[derive(Debug)]
+struct PendingDuplicate {
+    /// Index of the watcher rule.
+    rule: usize,
+    /// Whether it fired on the memory change rather than on the log event.
+    from_memory: bool,
+    fired_at: Instant,
+}
+
+impl RuleState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets everything, for example when a new attempt starts.
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.seen.clear();
+        self.duplicates.clear();
+    }
+
+    /// Returns whether the event is a sigil or a puzzle already seen in the current attempt.
+    pub fn is_repeated(&self, context: &Context) -> bool {
+        dedup_key(context).map(|x| self.seen.contains(&x)).unwrap_or(false)
+    }
+
+    /// Removes the pending duplicate of the rule from the given source, returning whether there
+    /// was one.
+    fn take_duplicate(&mut self, rule: usize, from_memory: bool) -> bool {
+        match self.duplicates
+                  .iter()
+                  .position(|x| x.rule == rule && x.from_memory == from_memory)
+        {
+            Some(index) => {
+                self.duplicates.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Returns the key for deduplicating the event, if it is a sigil or a puzzle.
+fn dedup_key(context: &Context) -> Option<(Option<String>, String)> {
+    match context.event {
+        Some(&LogEvent::ItemPicked(ref name)) |
+        Some(&LogEvent::PuzzleSolved(ref name)) => {
+            Some((context.world.map(|x| x.to_string()), name.clone()))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the actions of all rules that fire in the given context, in order.
+pub fn evaluate(rules: &[Rule], state: &mut RuleState, context: &Context) -> Vec<Action> {
+    state.counts.resize(rules.len(), 0);
+
+    let key = dedup_key(context);
+    let repeated = state.is_repeated(context);
+
+    let mut fired = Vec::new();
+
+    for (index, (rule, count)) in rules.iter().zip(state.counts.iter_mut()).enumerate() {
+        if !rule.matches(context) {
+            continue;
+        }
+
+        if rule.deduplicate && repeated {
+            continue;
+        }
+
+        *count += 1;
+
+        if rule.conditions.count.map(|x| x == *count).unwrap_or(true) {
+            fired.push((index, rule.action));
+        }
+    }
+
+    if let Some(key) = key {
+        state.seen.insert(key);
+    }
+
+    remove_duplicates(rules, state, context, fired)
+}
+
+/// Drops the actions of events that were already reported by the other source, remembering the
+/// ones that fired first.
+fn remove_duplicates(rules: &[Rule],
+                     state: &mut RuleState,
+                     context: &Context,
+                     mut fired: Vec<(usize, Action)>)
+                     -> Vec<Action> {
+    let now = Instant::now();
+    let window = Duration::from_secs(DUPLICATE_WINDOW_SECONDS);
+    state.duplicates.retain(|x| now.duration_since(x.fired_at) < window);
+
+    if context.change.is_some() {
+        fired.retain(|&(index, _)| {
+            if rules[index].duplicates.is_none() {
+                return true;
+            }
+            if state.take_duplicate(index, false) {
+                return false;
+            }
+
+            state.duplicates.push(PendingDuplicate {
+                                      rule: index,
+                                      from_memory: true,
+                                      fired_at: now,
+                                  });
+            true
+        });
+    } else {
+        for (index, rule) in rules.iter().enumerate() {
+            let duplicates = match rule.duplicates {
+                Some(ref duplicates) if duplicates.matches(context) &&
+                                        rule.conditions.matches(context) => duplicates,
+                _ => continue,
+            };
+
+            // Only the rule firing on the duplicate event counts, not others with the same action.
+            let position = match fired.iter().position(|&(fired_index, action)| {
+                action == rule.action && rules[fired_index].trigger.overlaps(duplicates)
+            }) {
+                Some(position) => position,
+                None => continue,
+            };
+
+            if state.take_duplicate(index, true) {
+                fired.remove(position);
+            } else {
+                state.duplicates.push(PendingDuplicate {
+                                          rule: index,
+                                          from_memory: false,
+                                          fired_at: now,
+                                      });
+            }
+        }
+    }
+
+    fired.into_iter().map(|(_, action)| action).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory::Value;
+
+    const A1: &str = "Content/Talos/Levels/Cloud_1_01.wld";
+    const A6: &str = "Content/Talos/Levels/Cloud_1_06.wld";
+
+    /// A log split on sigils outside A6, a memory split on the sigil count duplicating it and an
+    /// unrelated split on picking up DJ3.
+    fn rules() -> Vec<Rule> {
+        let mut watcher_rule = Rule::new(Trigger {
+                                             watcher: Some("sigils".to_string()),
+                                             increased: true,
+                                             ..Trigger::default()
+                                         },
+                                         Action::Split);
+        watcher_rule.duplicates = Some(Trigger::event(EventKind::ItemPicked));
+
+        let mut rules = vec![Rule::new(Trigger::event(EventKind::ItemPicked), Action::Split)
+                                 .excluding_worlds(vec![A6.to_string()]),
+                             watcher_rule,
+                             Rule::new(Trigger::regex(r"Picked: DJ3"), Action::Split)];
+        for rule in &mut rules {
+            rule.compile().unwrap();
+        }
+
+        rules
+    }
+
+    fn pick(rules: &[Rule], state: &mut RuleState, world: &str, item: &str) -> Vec<Action> {
+        let line = format!("12:00:00 INF:  Picked: {}", item);
+        let event = LogEvent::ItemPicked(item.to_string());
+        evaluate(rules,
+                 state,
+                 &Context {
+                      line: &line,
+                      event: Some(&event),
+                      world_changed: false,
+                      world: Some(world),
+                      previous_world: None,
+                      change: None,
+                  })
+    }
+
+    fn count_sigil(rules: &[Rule], state: &mut RuleState, world: &str, count: i64) -> Vec<Action> {
+        let change = WatcherChange {
+            name: "sigils".to_string(),
+            old: Value::Integer(count - 1),
+            new: Value::Integer(count),
+        };
+        evaluate(rules,
+                 state,
+                 &Context {
+                      line: "",
+                      event: None,
+                      world_changed: false,
+                      world: Some(world),
+                      previous_world: None,
+                      change: Some(&change),
+                  })
+    }
+
+    #[test]
+    fn memory_first_suppresses_log() {
+        let rules = rules();
+        let mut state = RuleState::new();
+        assert_eq!(count_sigil(&rules, &mut state, A1, 1), vec![Action::Split]);
+        assert_eq!(pick(&rules, &mut state, A1, "DJ1"), vec![]);
+        assert_eq!(pick(&rules, &mut state, A1, "DJ2"), vec![Action::Split]);
+    }
+
+    #[test]
+    fn log_first_suppresses_memory() {
+        let rules = rules();
+        let mut state = RuleState::new();
+        assert_eq!(pick(&rules, &mut state, A1, "DJ1"), vec![Action::Split]);
+        assert_eq!(count_sigil(&rules, &mut state, A1, 1), vec![]);
+        assert_eq!(count_sigil(&rules, &mut state, A1, 2), vec![Action::Split]);
+    }
+
+    #[test]
+    fn duplicates_expire() {
+        let rules = rules();
+        let mut state = RuleState::new();
+        assert_eq!(count_sigil(&rules, &mut state, A1, 1), vec![Action::Split]);
+        for duplicate in &mut state.duplicates {
+            duplicate.fired_at -= Duration::from_secs(DUPLICATE_WINDOW_SECONDS);
+        }
+        assert_eq!(pick(&rules, &mut state, A1, "DJ1"), vec![Action::Split]);
+    }
+
+    #[test]
+    fn unrelated_splits_are_kept() {
+        let rules = rules();
+        let mut state = RuleState::new();
+        // Only the unrelated rule fires in A6, so there is nothing to suppress.
+        assert_eq!(count_sigil(&rules, &mut state, A6, 1), vec![Action::Split]);
+        assert_eq!(pick(&rules, &mut state, A6, "DJ3"), vec![Action::Split]);
+    }
+
+    #[test]
+    fn log_events_outside_the_conditions_are_not_pending() {
+        let mut rules = rules();
+        rules[1].conditions.excluded_worlds = vec![A1.to_string()];
+        let mut state = RuleState::new();
+        assert_eq!(pick(&rules, &mut state, A1, "DJ1"), vec![Action::Split]);
+        assert!(state.duplicates.is_empty());
+    }
+}
@@ -1,45 +1,270 @@
 use std::{env, thread};
 use std::borrow::Cow;
 use std::cmp::{max, min};
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, Read};
-use std::sync::Arc;
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
 use std::time::Duration;
 use std::thread::JoinHandle;
 
+use chrono::{self, Local};
 use errors::*;
 use config::*;
-use game_time::GameTime;
+use game_time::{GameTime, WatcherValues};
+use learn::{self, Learner};
+use log_file::LogFile;
+use log_parser::{parse_line, parse_timestamp, LogEvent};
+use memory::WatcherChange;
+use presets::is_console_command;
+use route::RouteCheck;
+use rules::{evaluate, Action, Context, RuleState};
 use livesplit_core::{component, GeneralLayoutSettings, SharedTimer, TimeSpan, Timer, TimerPhase};
 use livesplit_core::run::{parser, saver};
 use livesplit_core::settings::SemanticColor;
-use notify::{op, raw_watcher, RawEvent, RecursiveMode, Watcher};
+use notify::{op, raw_watcher, RawEvent, RecommendedWatcher, RecursiveMode, Watcher};
 use pancurses;
-use regex::Regex;
 
-enum ArgumentPosition {
+pub enum ArgumentPosition {
     TalosLogFilename = 1,
     SplitsFilename = 2,
+    Mode = 3,
+    ModeArgument = 4,
 }
 
-struct GameState {
+/// Lines processed later than this after being logged are not corrected for.
+const MAX_LAG_SECONDS: i64 = 10;
+
+/// How often the changes of the watcher values are checked while the log is quiet.
+const WATCHER_CHECK_INTERVAL_MS: u64 = 15;
+
+/// Text describing the last autosplitter action, shown at the bottom of the timer.
+type StatusLine = Arc<Mutex<Option<String>>>;
+
+pub struct GameState {
     game_time: GameTime,
-    current_world: Option<String>,
+    pub current_world: Option<String>,
+    pub previous_world: Option<String>,
+    rule_state: RuleState,
+    /// The current segment when a run is in progress, for following the route.
+    segment: Option<usize>,
+}
+
+/// A disagreement between the route and the runner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteWarning {
+    /// The event would have split, but the route doesn't expect it.
+    OffRoute { expected: usize },
+    /// The event is expected for a later segment.
+    Ahead { expected: usize, reached: usize },
 }
 
 impl GameState {
-    fn new() -> Self {
+    pub fn new(watchers: WatcherValues) -> Self {
         Self {
-            game_time: GameTime::new(),
+            game_time: GameTime::new(watchers),
             current_world: None,
+            previous_world: None,
+            rule_state: RuleState::new(),
+            segment: None,
         }
     }
+
+    /// Updates the world state from the event, returning whether the current world changed.
+    fn update(&mut self, event: &LogEvent) -> bool {
+        match *event {
+            LogEvent::WorldChanged(ref world) | LogEvent::SimulationStarted(ref world) => {
+                if self.current_world.as_ref() == Some(world) {
+                    return false;
+                }
+
+                self.previous_world = self.current_world.take();
+                self.current_world = Some(world.clone());
+                true
+            }
+            LogEvent::SimulationStopped => {
+                self.previous_world = None;
+                self.current_world = None;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Updates the state from the log line, returning its event, the actions of the rules it
+    /// triggers and a warning if it doesn't follow the route.
+    fn evaluate_line(&mut self,
+                     config: &Config,
+                     line: &str)
+                     -> (Option<LogEvent>, Vec<Action>, Option<RouteWarning>) {
+        let event = parse_line(line);
+
+        let world_changed = event.as_ref().map(|x| self.update(x)).unwrap_or(false);
+
+        let (actions, warning) = {
+            let context = Context {
+                line,
+                event: event.as_ref(),
+                world_changed,
+                world: self.current_world.as_ref().map(|x| x.as_str()),
+                previous_world: self.previous_world.as_ref().map(|x| x.as_str()),
+                change: None,
+            };
+
+            let repeated = self.rule_state.is_repeated(&context);
+            let actions = evaluate(&config.rules, &mut self.rule_state, &context);
+            self.follow_route(config, &context, actions, repeated)
+        };
+
+        (event, actions, warning)
+    }
+
+    /// Returns the actions of the rules triggered by the change of a watcher value and a warning
+    /// if it doesn't follow the route.
+    fn evaluate_change(&mut self,
+                       config: &Config,
+                       change: &WatcherChange)
+                       -> (Vec<Action>, Option<RouteWarning>) {
+        let context = Context {
+            line: "",
+            event: None,
+            world_changed: false,
+            world: self.current_world.as_ref().map(|x| x.as_str()),
+            previous_world: self.previous_world.as_ref().map(|x| x.as_str()),
+            change: Some(change),
+        };
+
+        let actions = evaluate(&config.rules, &mut self.rule_state, &context);
+        self.follow_route(config, &context, actions, false)
+    }
+
+    /// Replaces the splits fired by the rules with the ones of the route, if there is one.
+    ///
+    /// The console commands always apply as they are. `repeated` tells whether the event was seen
+    /// before in the attempt.
+    fn follow_route(&self,
+                    config: &Config,
+                    context: &Context,
+                    mut actions: Vec<Action>,
+                    repeated: bool)
+                    -> (Vec<Action>, Option<RouteWarning>) {
+        let mut warning = None;
+
+        if context.event.map(is_console_command).unwrap_or(false) {
+            return (actions, warning);
+        }
+
+        if let (Some(route), Some(segment)) = (config.loaded_route.as_ref(), self.segment) {
+            let split = actions.contains(&Action::Split);
+            actions.retain(|x| *x != Action::Split);
+
+            match route.check(segment, context, repeated) {
+                RouteCheck::Expected => actions.push(Action::Split),
+                RouteCheck::Ahead(skipped) => {
+                    warning = Some(RouteWarning::Ahead {
+                                       expected: segment,
+                                       reached: segment + skipped,
+                                   });
+
+                    if config.route.mark_skipped_segments {
+                        for _ in 0..skipped {
+                            actions.push(Action::Skip);
+                        }
+                        actions.push(Action::Split);
+                    }
+                }
+                RouteCheck::OffRoute => {
+                    if split {
+                        warning = Some(RouteWarning::OffRoute { expected: segment });
+                    }
+                }
+            }
+        }
+
+        (actions, warning)
+    }
+
+    /// Updates the state after the action was applied to the timer.
+    fn action_applied(&mut self, action: Action) {
+        match action {
+            Action::Start => {
+                self.segment = Some(0);
+                self.rule_state.clear();
+            }
+            Action::Reset | Action::ResetWithoutGolds => {
+                self.segment = None;
+                self.rule_state.clear();
+            }
+            Action::Split | Action::Skip => self.segment = self.segment.map(|x| x + 1),
+            Action::Undo => self.segment = self.segment.map(|x| x.saturating_sub(1)),
+            Action::Finish => self.segment = None,
+            _ => {}
+        }
+    }
+
+    /// Forgets the current and previous worlds, for example when the game restarts.
+    fn forget_worlds(&mut self) {
+        self.current_world = None;
+        self.previous_world = None;
+    }
+
+    /// Rebuilds the state from the existing log contents without touching the timer.
+    ///
+    /// Returns the number of splits done so far if the log ends after a run was started. The run
+    /// might have been finished if this is not less than the number of segments.
+    fn rebuild(&mut self, config: &Config, log: &mut LogFile) -> Result<Option<usize>> {
+        let mut splits = None;
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            if log.read_line(&mut line)? == 0 {
+                break;
+            }
+
+            for action in self.evaluate_line(config, &line).1 {
+                let applied = match (action, splits) {
+                    (Action::Start, None) => {
+                        splits = Some(0);
+                        true
+                    }
+                    (Action::Split, Some(x)) | (Action::Skip, Some(x)) => {
+                        splits = Some(x.saturating_add(1));
+                        true
+                    }
+                    (Action::Finish, Some(_)) => {
+                        splits = Some(usize::max_value());
+                        true
+                    }
+                    (Action::Undo, Some(x)) if x > 0 => {
+                        splits = Some(x - 1);
+                        true
+                    }
+                    (Action::Reset, _) |
+                    (Action::ResetWithoutGolds, _) => {
+                        splits = None;
+                        true
+                    }
+                    _ => false,
+                };
+
+                if applied {
+                    self.action_applied(action);
+                }
+            }
+        }
+
+        Ok(splits)
+    }
 }
 
 fn save_splits(timer: &Timer) -> Result<()> {
-    let splits_filename = env::args().nth(ArgumentPosition::SplitsFilename as usize)
-                                     .ok_or("the splits filename argument is missing")?;
+    if learning_requested() {
+        // The splits file is written by the learner once the run is finished.
+        return Ok(());
+    }
+
+    let splits_filename = splits_filename()?;
 
     saver::livesplit::save(timer.run(),
                            File::create(splits_filename)
@@ -49,69 +274,68 @@ fn save_splits(timer: &Timer) -> Result<()> {
     Ok(())
 }
 
-fn process_line(config: &Config,
-                timer: &SharedTimer,
-                state: &mut GameState,
-                line: &str)
-                -> Result<()> {
-    lazy_static! {
-        static ref CHANGING_OVER_TO: Regex =
-            Regex::new(r"Changing over to (.+)").unwrap();
-        static ref PUZZLE_SOLVED: Regex =
-            Regex::new(r#"Puzzle "[^"]+" solved"#).unwrap();
-    }
-
-    if let Some(caps) = CHANGING_OVER_TO.captures(line) {
-        // Splitting on returning to Nexus.
-        let world_name = caps.get(1).unwrap().as_str();
-        if config.split_on_return_to_nexus && world_name == "Content/Talos/Levels/Nexus.wld" {
-            let mut timer = timer.write();
-            if timer.current_phase() == TimerPhase::Running {
-                if state.current_world.as_ref().unwrap() != world_name {
-                    timer.split();
-                }
-            }
-        }
+/// Returns true if the timer should replay a log instead of watching it.
+pub fn replay_requested() -> bool {
+    env::args().nth(ArgumentPosition::Mode as usize)
+               .map(|x| x == "--replay")
+               .unwrap_or(false)
+}
 
-        state.current_world = Some(world_name.to_string());
-    } else if line.contains("Picked:") {
-        // Splitting on tetromino and star pickups.
-        if config.split_on_sigil_collection.in_general {
-            let world_name = state.current_world.as_ref().unwrap();
+/// Returns the world to time if the timer should run in the individual level mode.
+pub fn individual_level_requested() -> Result<Option<String>> {
+    if env::args().nth(ArgumentPosition::Mode as usize) != Some("--il".to_string()) {
+        return Ok(None);
+    }
 
-            let mut split = true;
-            if world_name == "Content/Talos/Levels/Cloud_1_06.wld" {
-                if !config.split_on_sigil_collection.in_a6 {
-                    split = false;
-                }
-            } else if world_name == "Content/Talos/Levels/Cloud_2_04.wld" {
-                if !config.split_on_sigil_collection.in_b4 {
-                    split = false;
-                }
-            }
+    match env::args().nth(ArgumentPosition::ModeArgument as usize) {
+        Some(world) => Ok(Some(world)),
+        None => bail!("--il needs the world to time, for example --il A6"),
+    }
+}
 
-            if split {
-                timer.write().split();
-            }
-        }
-    } else if PUZZLE_SOLVED.is_match(line) {
-        // Splitting on tetromino puzzles.
-        if config.split_on_tetromino_doors {
-            timer.write().split();
-        }
-    } else if line.contains("Started simulation on 'Content/Talos/Levels/Cloud_1_01.wld'") {
-        // Starting the timer.
-        let mut timer_ = timer.write();
-        if timer_.current_phase() == TimerPhase::NotRunning {
-            timer_.start();
+/// Returns true if the splits should be learned from the run.
+pub fn learning_requested() -> bool {
+    env::args().nth(ArgumentPosition::Mode as usize)
+               .map(|x| x == "--learn")
+               .unwrap_or(false)
+}
+
+/// The outcome of processing a log line or a change of a watcher value.
+pub struct ProcessedLine {
+    pub event: Option<LogEvent>,
+    /// The timer actions that were fired.
+    pub actions: Vec<Action>,
+    /// A warning if the line doesn't follow the route.
+    pub warning: Option<String>,
+    /// How long after being logged the line was processed, going by its timestamp. The split
+    /// times are moved back by this much.
+    ///
+    /// This is `None` if the game was loading during that time, as the game time didn't advance
+    /// by the whole lag then.
+    pub lag: Option<TimeSpan>,
+}
 
-            // Try starting the game time.
-            drop(timer_);
-            state.game_time.start(timer.clone());
+/// Processes a single log line, applying the timer actions it fires.
+///
+/// Saving the splits and starting the game time are left to the caller.
+pub fn process_line(config: &Config,
+                    timer: &SharedTimer,
+                    state: &mut GameState,
+                    line: &str)
+                    -> Result<ProcessedLine> {
+    let (event, actions, warning) = state.evaluate_line(config, line);
+    let warning = warning.map(|x| describe_route_warning(&timer.read(), x));
+    let lag = if config.log_timestamps && !actions.is_empty() {
+        match line_lag(line) {
+            Some(lag) if !timer.read().is_game_time_paused() &&
+                         !state.game_time.resumed_within(lag) => Some(lag),
+            _ => None,
         }
+    } else {
+        None
+    };
 
-        state.current_world = Some("Content/Talos/Levels/Cloud_1_01.wld".to_string());
-    } else if line.contains("Save Talos Progress: delayed request") {
+    if event == Some(LogEvent::ProgressSaved) && config.individual_level.is_none() {
         // Resuming the game time on intro cutscene finish.
         let mut timer = timer.write();
         if timer.current_phase() == TimerPhase::Running && timer.is_game_time_initialized() &&
@@ -119,95 +343,470 @@ fn process_line(config: &Config,
         {
             timer.unpause_game_time();
         }
-    } else if line.contains("Stopping simulation (duration: ") {
-        // Resetting.
-        let mut timer = timer.write();
-        timer.reset(true);
-        save_splits(&timer)?;
-
-        state.current_world = None;
-    } else if line.contains("USER: /eternalize") || line.contains("USER: /transcend") {
-        // Splitting on game end.
-        if state.current_world
-                .as_ref()
-                .map(|world| world == "Content/Talos/Levels/Nexus.wld")
-                .unwrap_or(false)
-        {
-            timer.write().split();
-        }
-    } else if line.contains("USER:") {
-        // Splitting on game end.
-        if state.current_world
-                .as_ref()
-                .map(|world| world == "Content/Talos/Levels/Islands_03.wld")
-                .unwrap_or(false)
-        {
-            timer.write().split();
+    }
+
+    Ok(ProcessedLine {
+           event,
+           actions: apply_actions(config, timer, state, actions, lag),
+           warning,
+           lag,
+       })
+}
+
+/// Processes a change of a watcher value, applying the timer actions it fires.
+pub fn process_change(config: &Config,
+                      timer: &SharedTimer,
+                      state: &mut GameState,
+                      change: &WatcherChange)
+                      -> Result<ProcessedLine> {
+    let (actions, warning) = state.evaluate_change(config, change);
+    let warning = warning.map(|x| describe_route_warning(&timer.read(), x));
+
+    Ok(ProcessedLine {
+           event: None,
+           actions: apply_actions(config, timer, state, actions, None),
+           warning,
+           lag: None,
+       })
+}
+
+/// Applies the actions to the timer, returning the ones that had an effect.
+fn apply_actions(config: &Config,
+                 timer: &SharedTimer,
+                 state: &mut GameState,
+                 actions: Vec<Action>,
+                 lag: Option<TimeSpan>)
+                 -> Vec<Action> {
+    let mut fired = Vec::new();
+    for action in actions {
+        let applied = if config.learning {
+            apply_learning_action(timer, action)
+        } else {
+            apply_action(timer, action, lag)
+        };
+
+        if applied {
+            fired.push(action);
+            state.action_applied(action);
         }
     }
 
-    Ok(())
+    fired
+}
+
+/// Returns how long ago the line was logged, going by its timestamp.
+///
+/// Timestamps are rounded down, so only the lag beyond their resolution is counted, which never
+/// moves the split times back too far.
+fn line_lag(line: &str) -> Option<TimeSpan> {
+    let (logged, resolution) = match parse_timestamp(line) {
+        Some(x) => x,
+        None => return None,
+    };
+
+    let mut lag = Local::now().naive_local().time() - logged;
+    if lag < -chrono::Duration::hours(12) {
+        // The line was logged before midnight.
+        lag = lag + chrono::Duration::days(1);
+    }
+    lag = lag - resolution;
+
+    // Anything longer means the clocks disagree or the line is old.
+    if lag <= chrono::Duration::zero() || lag > chrono::Duration::seconds(MAX_LAG_SECONDS) {
+        return None;
+    }
+
+    Some(TimeSpan::from_milliseconds(lag.num_milliseconds() as f64))
+}
+
+fn describe_route_warning(timer: &Timer, warning: RouteWarning) -> String {
+    let segment_name = |index: usize| {
+        timer.run()
+             .segments()
+             .get(index)
+             .map(|x| x.name().to_string())
+             .unwrap_or_else(|| format!("segment {}", index + 1))
+    };
+
+    match warning {
+        RouteWarning::OffRoute { expected } => {
+            format!("off route, expected {}", segment_name(expected))
+        }
+        RouteWarning::Ahead { expected, reached } => {
+            format!("ahead of route: {} instead of {}",
+                    segment_name(reached),
+                    segment_name(expected))
+        }
+    }
+}
+
+/// Applies the action when learning the splits. The timer has a single segment, so splitting
+/// and undoing are left to the learner.
+fn apply_learning_action(timer: &SharedTimer, action: Action) -> bool {
+    match action {
+        Action::Split | Action::Undo => timer.read().current_phase() == TimerPhase::Running,
+        Action::Skip => false,
+        _ => apply_action(timer, action, None),
+    }
+}
+
+/// Splits as if it happened `lag` ago.
+///
+/// Only the game time can be moved back, so the real time split is left as it is. The learner
+/// moves back both, as it keeps its own times.
+fn split_with_lag(timer: &mut Timer, lag: Option<TimeSpan>) {
+    match (lag, timer.current_time().game_time) {
+        (Some(lag), Some(game_time)) if timer.is_game_time_initialized() && lag < game_time => {
+            timer.set_game_time(game_time - lag);
+            timer.split();
+            timer.set_game_time(game_time);
+        }
+        _ => timer.split(),
+    }
+}
+
+/// Applies the action to the timer, returning whether it had any effect.
+///
+/// Splits are moved back by `lag`.
+fn apply_action(timer: &SharedTimer, action: Action, lag: Option<TimeSpan>) -> bool {
+    let mut timer = timer.write();
+    let phase = timer.current_phase();
+
+    match action {
+        Action::Start => {
+            if phase != TimerPhase::NotRunning {
+                return false;
+            }
+
+            timer.start();
+        }
+        Action::Split => {
+            if phase != TimerPhase::Running {
+                return false;
+            }
+
+            split_with_lag(&mut timer, lag);
+        }
+        Action::Finish => {
+            if phase != TimerPhase::Running {
+                return false;
+            }
+
+            // Skipping doesn't do anything on the last split.
+            for _ in 0..timer.run().segments().len() {
+                timer.skip_split();
+            }
+            split_with_lag(&mut timer, lag);
+        }
+        Action::Skip => {
+            if phase != TimerPhase::Running {
+                return false;
+            }
+
+            timer.skip_split();
+        }
+        Action::Undo => {
+            if phase == TimerPhase::NotRunning {
+                return false;
+            }
+
+            timer.undo_split();
+        }
+        Action::Reset => {
+            timer.reset(true);
+        }
+        Action::ResetWithoutGolds => {
+            timer.reset(false);
+        }
+        Action::Pause => {
+            if phase != TimerPhase::Running {
+                return false;
+            }
+
+            timer.pause();
+        }
+        Action::Resume => {
+            if phase != TimerPhase::Paused {
+                return false;
+            }
+
+            timer.resume();
+        }
+        Action::PauseGameTime => {
+            if phase != TimerPhase::Running || !timer.is_game_time_initialized() {
+                return false;
+            }
+
+            timer.pause_game_time();
+        }
+        Action::ResumeGameTime => {
+            if phase != TimerPhase::Running || !timer.is_game_time_initialized() {
+                return false;
+            }
+
+            timer.unpause_game_time();
+        }
+    }
+
+    true
 }
 
-fn watch_log(config: &Config, timer: SharedTimer) -> Result<()> {
+fn watch_log(config: &Config,
+             timer: SharedTimer,
+             status: &StatusLine,
+             watchers: WatcherValues)
+             -> Result<()> {
     let log_filename = env::args().nth(ArgumentPosition::TalosLogFilename as usize)
                                   .ok_or("the log filename argument is missing")?;
-    let log = OpenOptions::new().read(true)
-                                .write(true)
-                                .create(true)
-                                .truncate(true)
-                                .open(&log_filename)
-                                .chain_err(|| "could not open the Talos log file")?;
-    let mut log = BufReader::new(log);
+    let mut log = LogFile::open(&log_filename)?;
     let mut line = String::new();
-    {
-        let mut temp = Vec::new();
-        log.read_to_end(&mut temp)
-           .chain_err(|| "error reading the Talos log file")?;
-    }
 
-    let (tx, rx) = channel();
-    let mut watcher = raw_watcher(tx).chain_err(|| "could not create a filesystem watcher")?;
-    watcher.watch(&log_filename, RecursiveMode::NonRecursive)
-           .chain_err(|| "could not set up the filesystem watcher on the Talos log file")?;
+    let mut state = GameState::new(watchers);
+    let mut learner = Learner::new();
+    match config.log_start {
+        LogStart::End => log.seek_to_end()?,
+        LogStart::Replay => {
+            let splits = state.rebuild(config, &mut log)?;
+            let segment_count = timer.read().run().segments().len();
+            if let Some(splits) = splits {
+                // The learner can't know the segments before the restart.
+                if splits < segment_count && !config.learning {
+                    // A run is in progress: pick up where it left off, skipping the finished
+                    // segments.
+                    {
+                        let mut timer = timer.write();
+                        timer.start();
+
+                        for _ in 0..splits {
+                            timer.skip_split();
+                        }
+                    }
+
+                    // Past the first segment the intro is long over, so the game time is
+                    // tracked right away.
+                    let wait_for_intro = config.individual_level.is_none() && splits == 0;
+                    let message = state.game_time.start(timer.clone(),
+                                                        wait_for_intro,
+                                                        &config.game_versions);
+                    if message.is_some() {
+                        *status.lock().unwrap() = message;
+                    }
+                } else {
+                    // The run is not resumed.
+                    state.segment = None;
+                }
+            }
+        }
+    }
 
-    // TODO: perhaps update the state from the existing log?
-    let mut state = GameState::new();
+    // Watch the directory rather than the file so that the file being deleted and recreated by
+    // the game is noticed.
+    let directory = match log.path().parent() {
+        Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let (mut rx, mut _watcher) = watch_directory(&directory)?;
 
     loop {
-        match rx.recv() {
-            Ok(RawEvent { op: Ok(op), .. }) => {
-                if op != op::WRITE {
+        // The timer can also be reset from the terminal.
+        if state.game_time.is_running() && timer.read().current_phase() == TimerPhase::NotRunning {
+            state.game_time.stop();
+        }
+
+        for notice in state.game_time.notices() {
+            *status.lock().unwrap() = Some(notice);
+        }
+
+        for change in state.game_time.watcher_changes() {
+            let processed = process_change(config, &timer, &mut state, &change)?;
+            report_actions(config,
+                           &timer,
+                           &mut state,
+                           status,
+                           &mut learner,
+                           processed,
+                           Some(change.to_string()),
+                           change.to_string())?;
+        }
+
+        match rx.recv_timeout(Duration::from_millis(WATCHER_CHECK_INTERVAL_MS)) {
+            Ok(RawEvent { path, op: Ok(op), .. }) => {
+                if let Some(path) = path {
+                    if path.file_name() != log.path().file_name() {
+                        continue;
+                    }
+                }
+
+                if !op.intersects(op::WRITE | op::CREATE | op::REMOVE | op::RENAME) {
                     continue;
                 }
+            }
+            Ok(RawEvent { op: Err(_), .. }) => {
+                // Events might have been missed, check the file anyway.
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                // The watcher is gone, set it up again.
+                let (new_rx, new_watcher) = watch_directory(&directory)?;
+                rx = new_rx;
+                _watcher = new_watcher;
+            }
+        }
 
-                loop {
-                    line.clear();
-                    let length = log.read_line(&mut line)
-                                    .chain_err(|| "error reading the Talos log file")?;
-                    if length == 0 {
-                        break;
-                    }
+        if log.reopen_if_replaced()? {
+            // The game has restarted. While the game time is tracked, it finds the game on its own.
+            state.forget_worlds();
+            if !state.game_time.is_running() {
+                state.game_time.reset();
+            }
+        }
+
+        loop {
+            line.clear();
+            if log.read_line(&mut line)? == 0 {
+                break;
+            }
+
+            let world = state.current_world.clone();
+            let processed = process_line(config, &timer, &mut state, &line)?;
+            let description = processed.event.as_ref().map(|x| config.names.describe(x));
+            // Named after the world the line was read in.
+            let segment_name = processed.event
+                                        .as_ref()
+                                        .map(|x| {
+                                                 let world = world.as_ref().map(|x| x.as_str());
+                                                 config.names.segment_name(x, world)
+                                             })
+                                        .unwrap_or_else(|| line.trim().to_string());
+            report_actions(config,
+                           &timer,
+                           &mut state,
+                           status,
+                           &mut learner,
+                           processed,
+                           description,
+                           segment_name)?;
+        }
+    }
+}
+
+/// Shows the fired actions in the status line and follows them up: records the splits when
+/// learning, starts the game time and saves the splits on reset.
+fn report_actions(config: &Config,
+                  timer: &SharedTimer,
+                  state: &mut GameState,
+                  status: &StatusLine,
+                  learner: &mut Learner,
+                  processed: ProcessedLine,
+                  description: Option<String>,
+                  segment_name: String)
+                  -> Result<()> {
+    let ProcessedLine {
+        actions,
+        warning,
+        lag,
+        ..
+    } = processed;
 
-                    process_line(config, &timer, &mut state, &line)?;
+    for action in actions {
+        if let Some(ref description) = description {
+            let mut text = format!("{}: {}", action.name(), description);
+            match (lag, action) {
+                (Some(lag), Action::Split) |
+                (Some(lag), Action::Finish) => {
+                    text += &format!(" ({:.2} s late)", lag.total_seconds())
                 }
+                _ => {}
             }
-            _ => {
-                panic!("error receiving event");
+            *status.lock().unwrap() = Some(text);
+        }
+
+        if config.learning {
+            match action {
+                Action::Start | Action::Reset | Action::ResetWithoutGolds => learner.clear(),
+                Action::Split => {
+                    learner.record(segment_name.clone(), timer.read().current_time(), lag)
+                }
+                Action::Undo => learner.undo(),
+                Action::Finish => {
+                    learner.record(segment_name.clone(), timer.read().current_time(), lag)
+                }
+                _ => {}
+            }
+
+            // The run might end other ways than on a finishing rule, so save once it has ended.
+            if timer.read().current_phase() == TimerPhase::Ended && !learner.is_saved() {
+                let category_name = timer.read().run().category_name().to_string();
+                learner.save(&category_name, &splits_filename()?)?;
+                *status.lock().unwrap() = Some(format!("learned {} segments", learner.len()));
+            }
+        }
+
+        match action {
+            Action::Start => {
+                // Try starting the game time.
+                let message = state.game_time.start(timer.clone(),
+                                                    config.individual_level.is_none(),
+                                                    &config.game_versions);
+                if message.is_some() {
+                    *status.lock().unwrap() = message;
+                }
+            }
+            Action::Reset | Action::ResetWithoutGolds => {
+                state.game_time.stop();
+                save_splits(&timer.read())?
             }
+            _ => {}
         }
     }
+
+    if let Some(warning) = warning {
+        *status.lock().unwrap() = Some(format!("warning: {}", warning));
+    }
+
+    Ok(())
 }
 
-fn watch_log_thread(watch_to_main_tx: Sender<Error>, config: Arc<Config>, timer: SharedTimer) {
-    if let Err(e) = watch_log(&config, timer) {
+fn watch_directory(directory: &Path) -> Result<(Receiver<RawEvent>, RecommendedWatcher)> {
+    let (tx, rx) = channel();
+    let mut watcher = raw_watcher(tx).chain_err(|| "could not create a filesystem watcher")?;
+    watcher.watch(directory, RecursiveMode::NonRecursive)
+           .chain_err(|| "could not set up the filesystem watcher on the Talos log directory")?;
+
+    Ok((rx, watcher))
+}
+
+fn watch_log_thread(watch_to_main_tx: Sender<Error>,
+                    config: Arc<Config>,
+                    timer: SharedTimer,
+                    status: StatusLine,
+                    watchers: WatcherValues) {
+    if let Err(e) = watch_log(&config, timer, &status, watchers) {
         watch_to_main_tx.send(e).unwrap();
     }
 }
 
-fn create_timer() -> Result<Timer> {
-    let splits_filename = env::args().nth(ArgumentPosition::SplitsFilename as usize)
-                                     .ok_or("the splits filename argument is missing")?;
+pub fn splits_filename() -> Result<String> {
+    Ok(env::args().nth(ArgumentPosition::SplitsFilename as usize)
+                  .ok_or("the splits filename argument is missing")?)
+}
+
+/// Makes sure the route, if any, has a step for every segment.
+pub fn check_route(config: &Config, timer: &Timer) -> Result<()> {
+    if let Some(ref route) = config.loaded_route {
+        let segment_count = timer.run().segments().len();
+        if route.len() != segment_count {
+            bail!("the route has {} steps, but the splits have {} segments",
+                  route.len(),
+                  segment_count);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn create_timer() -> Result<Timer> {
+    let splits_filename = splits_filename()?;
     let splits = File::open(splits_filename).chain_err(|| "could not open the splits file")?;
     let run =
         parser::livesplit::parse(splits, None).chain_err(|| "could not parse the splits file")?;
@@ -319,7 +918,25 @@ fn draw_sum_of_best(window: &pancurses::Window,
     window.printw(&sob_state.time);
 }
 
+fn draw_status(window: &pancurses::Window, width: usize, status: Option<&String>) {
+    window.color_set(SemanticColor::Default as i16);
+    window.printw(&format!("{:1$.1$}", status.map(|x| x.as_str()).unwrap_or(""), width));
+}
+
+/// Draws the values read from the game memory, one per line.
+fn draw_watchers(window: &pancurses::Window, width: usize, watchers: &WatcherValues) {
+    window.color_set(SemanticColor::Default as i16);
+    for (name, value) in watchers.lock().unwrap().iter() {
+        let value = value.to_string();
+        let name_width = width.saturating_sub(value.len() + 1);
+        let line = format!("{:2$.2$} {}", name, value, name_width);
+        window.printw(&format!("{:1$.1$}", line, width));
+    }
+}
+
 fn main_loop(timer: SharedTimer,
+             status: StatusLine,
+             watchers: WatcherValues,
              window: &pancurses::Window,
              watch_to_main_rx: Receiver<Error>,
              watch_thread: JoinHandle<()>)
@@ -361,8 +978,9 @@ fn main_loop(timer: SharedTimer,
             }
         }
 
+        let watcher_count = watchers.lock().unwrap().len();
         splits_component.settings_mut().visual_split_count =
-            max(window.get_max_y() as usize, 7) - 6;
+            max(window.get_max_y() as usize, 8 + watcher_count) - 7 - watcher_count;
 
         let timer = timer.read();
         let title_state = title_component.state(&timer);
@@ -393,6 +1011,12 @@ fn main_loop(timer: SharedTimer,
         // Draw sum of best.
         draw_sum_of_best(window, width, sob_state);
 
+        // Draw the values read from the game.
+        draw_watchers(window, width, &watchers);
+
+        // Draw the last autosplitter action.
+        draw_status(window, width, status.lock().unwrap().as_ref());
+
         window.refresh();
 
         thread::sleep(Duration::from_millis(10));
@@ -402,16 +1026,48 @@ fn main_loop(timer: SharedTimer,
 }
 
 pub fn run() -> Result<()> {
-    let config = read_config().chain_err(|| "failed to read config")
-                              .map(Arc::new)?;
+    let mut config = read_config().chain_err(|| "failed to read config")?;
+    config.learning = learning_requested();
+    let timer = if config.learning {
+        let splits_filename = splits_filename()?;
+        if Path::new(&splits_filename).exists() {
+            bail!("{} already exists, learning needs a new splits file",
+                  splits_filename);
+        }
+
+        // There is no splits file category name to match yet.
+        let category_name = match config.category {
+            Some(ref name) if !name.eq_ignore_ascii_case("auto") => name.as_str(),
+            _ => "",
+        };
+        learn::create_timer(category_name)?
+    } else {
+        create_timer()?
+    };
+    config.individual_level = individual_level_requested()?;
+    config.load_route(&splits_filename()?)
+          .chain_err(|| "failed to read the route")?;
+    config.select_rules(timer.run().category_name())
+          .chain_err(|| "failed to read config")?;
+    check_route(&config, &timer)?;
+    let config = Arc::new(config);
 
     let (watch_to_main_tx, watch_to_main_rx) = channel();
 
-    let timer = create_timer()?.into_shared();
+    let timer = timer.into_shared();
+    let status = StatusLine::default();
+    if !config.warnings.is_empty() {
+        *status.lock().unwrap() = Some(format!("warning: {}", config.warnings.join("; ")));
+    }
+    let watchers = WatcherValues::default();
     let watch_thread = {
         let config = config.clone();
         let timer = timer.clone();
-        thread::spawn(move || watch_log_thread(watch_to_main_tx, config, timer))
+        let status = status.clone();
+        let watchers = watchers.clone();
+        thread::spawn(move || {
+                          watch_log_thread(watch_to_main_tx, config, timer, status, watchers)
+                      })
     };
 
     let window = pancurses::initscr();
@@ -422,7 +1078,7 @@ pub fn run() -> Result<()> {
     pancurses::use_default_colors();
     init_curses_colors();
 
-    let result = main_loop(timer, &window, watch_to_main_rx, watch_thread);
+    let result = main_loop(timer, status, watchers, &window, watch_to_main_rx, watch_thread);
 
     pancurses::endwin();
 
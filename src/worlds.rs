@@ -0,0 +1,36 @@
+/// Community names of the worlds, along with their world files.
+///
+/// More worlds can be added with the `world_names` config option.
+pub const WORLDS: &[(&str, &str)] = &[("A1", "Content/Talos/Levels/Cloud_1_01.wld"),
+                                      ("A2", "Content/Talos/Levels/Cloud_1_02.wld"),
+                                      ("A3", "Content/Talos/Levels/Cloud_1_03.wld"),
+                                      ("A4", "Content/Talos/Levels/Cloud_1_04.wld"),
+                                      ("A5", "Content/Talos/Levels/Cloud_1_05.wld"),
+                                      ("A6", "Content/Talos/Levels/Cloud_1_06.wld"),
+                                      ("A7", "Content/Talos/Levels/Cloud_1_07.wld"),
+                                      ("B1", "Content/Talos/Levels/Cloud_2_01.wld"),
+                                      ("B2", "Content/Talos/Levels/Cloud_2_02.wld"),
+                                      ("B3", "Content/Talos/Levels/Cloud_2_03.wld"),
+                                      ("B4", "Content/Talos/Levels/Cloud_2_04.wld"),
+                                      ("B5", "Content/Talos/Levels/Cloud_2_05.wld"),
+                                      ("B6", "Content/Talos/Levels/Cloud_2_06.wld"),
+                                      ("B7", "Content/Talos/Levels/Cloud_2_07.wld"),
+                                      ("B8", "Content/Talos/Levels/Cloud_2_08.wld"),
+                                      ("C1", "Content/Talos/Levels/Cloud_3_01.wld"),
+                                      ("C2", "Content/Talos/Levels/Cloud_3_02.wld"),
+                                      ("C3", "Content/Talos/Levels/Cloud_3_03.wld"),
+                                      ("C4", "Content/Talos/Levels/Cloud_3_04.wld"),
+                                      ("C5", "Content/Talos/Levels/Cloud_3_05.wld"),
+                                      ("C6", "Content/Talos/Levels/Cloud_3_06.wld"),
+                                      ("C7", "Content/Talos/Levels/Cloud_3_07.wld"),
+                                      ("C8", "Content/Talos/Levels/Cloud_3_08.wld"),
+                                      ("Nexus", "Content/Talos/Levels/Nexus.wld"),
+                                      ("Tower 1", "Content/Talos/Levels/Tower_01.wld"),
+                                      ("Tower 2", "Content/Talos/Levels/Tower_02.wld"),
+                                      ("Tower 3", "Content/Talos/Levels/Tower_03.wld"),
+                                      ("Tower 4", "Content/Talos/Levels/Tower_04.wld"),
+                                      ("Tower 5", "Content/Talos/Levels/Tower_05.wld"),
+                                      ("Tower 6", "Content/Talos/Levels/Tower_06.wld"),
+                                      ("Messenger 1", "Content/Talos/Levels/Islands_01.wld"),
+                                      ("Messenger 2", "Content/Talos/Levels/Islands_02.wld"),
+                                      ("Messenger 3", "Content/Talos/Levels/Islands_03.wld")];